@@ -0,0 +1,52 @@
+use bppt::Notes;
+use bppt_wav::{Channel, Signal, Track};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use meval::Expr;
+use std::{num::NonZeroU16, str::FromStr};
+
+/// A track of `channels` cheap channels of different lengths, so that the cost is dominated by the mixing.
+fn track(channels: usize) -> Track {
+    Track::new(
+        NonZeroU16::new(120).unwrap(),
+        (0..channels)
+            .map(|i| {
+                Channel::new(
+                    Signal(Expr::from_str("t").unwrap()),
+                    Notes::parse("aAbcCdDefFgG", &"a".repeat(1 + i % 4)).unwrap(),
+                    442.0,
+                )
+            })
+            .collect(),
+    )
+}
+
+fn mixing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mix");
+    group.sample_size(10);
+    for channels in [1, 8, 32, 64] {
+        let mut track = track(channels);
+        group.bench_with_input(BenchmarkId::from_parameter(channels), &channels, |b, _| {
+            b.iter(|| track.mix().unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// A single channel of many short notes, so that the cost is dominated by the evaluation of the signal.
+fn signal(c: &mut Criterion) {
+    let mut track = Track::new(
+        NonZeroU16::new(120).unwrap(),
+        vec![Channel::new(
+            Signal(Expr::from_str("sin(2*pi*f*t)").unwrap()),
+            Notes::parse("aAbcCdDefFgG", "$64(900a)").unwrap(),
+            442.0,
+        )],
+    );
+    let mut group = c.benchmark_group("signal");
+    group.sample_size(10);
+    group.bench_function("sine", |b| b.iter(|| track.mix().unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, mixing, signal);
+criterion_main!(benches);
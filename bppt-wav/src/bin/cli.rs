@@ -1,9 +1,14 @@
 use anyhow::Result;
-use bppt_wav::{export, play, Channel, Signal, Track};
-use clap::{arg, Parser, Subcommand};
+use bppt::Notes;
+use bppt_wav::{
+    export_stream, play_track, Channel, Clipping, ExportOptions, Format, Signal, Track,
+};
+use clap::{Parser, Subcommand};
 use meval::Expr;
-use serde_json::from_str;
-use std::{fs::read_to_string, str::FromStr};
+use std::{fs::read_to_string, num::NonZeroU32, str::FromStr};
+
+#[path = "cli/diagnostics.rs"]
+mod diagnostics;
 
 /// Argument parser entry point
 #[derive(Parser)]
@@ -11,6 +16,12 @@ use std::{fs::read_to_string, str::FromStr};
 pub(crate) struct Cli {
     #[command(subcommand)]
     cmd: Command,
+    /// Seed of the random choices of every channel, replacing the ones of the JSON file
+    #[arg(long, global = true, value_name = "SEED")]
+    seed: Option<u64>,
+    /// Sample rate of the mix in Hz, replacing the one of the JSON file
+    #[arg(long, global = true, value_name = "HZ")]
+    sample_rate: Option<NonZeroU32>,
 }
 
 #[derive(Subcommand)]
@@ -23,6 +34,15 @@ enum Command {
         /// Root of the generated folder structure
         #[arg(value_name = "EXPORT_FOLDER")]
         out: String,
+        /// Sample format: 8, 16, 24 or 32-bit integers, or f32 for floats
+        #[arg(long, value_name = "FORMAT", default_value = "16")]
+        format: Format,
+        /// Don't dither integer samples
+        #[arg(long)]
+        no_dither: bool,
+        /// What to do with samples beyond full scale: clip, error or normalise
+        #[arg(long, value_name = "POLICY", default_value = "clip")]
+        clipping: Clipping,
     },
     /// Just play generated albums and tracks ~in order~ (hopefully)
     Play {
@@ -43,35 +63,71 @@ enum Command {
 
 impl Cli {
     pub(crate) fn look_what_to_do_and_do_it() -> Result<()> {
-        match Self::parse().cmd {
-            Command::Export { r#in, out } => export(
-                from_str::<Track>(read_to_string(r#in)?.as_str())?.mix()?,
+        let cli = Self::parse();
+        let seeded = |mut track: Track| {
+            if let Some(seed) = cli.seed {
+                track
+                    .channels
+                    .iter_mut()
+                    .for_each(|channel| channel.notes.seed = seed);
+            }
+            if let Some(sample_rate) = cli.sample_rate {
+                track.sample_rate = sample_rate;
+            }
+            track
+        };
+        match cli.cmd {
+            Command::Export {
+                r#in,
                 out,
-            ),
+                format,
+                no_dither,
+                clipping,
+            } => {
+                let (json, track) = load(&r#in)?;
+                export_stream(
+                    &mut seeded(track),
+                    out,
+                    ExportOptions {
+                        format,
+                        dither: !no_dither,
+                        clipping,
+                    },
+                )
+                .map_err(|err| diagnostics::report(&r#in, &json, err))
+            }
             Command::Play { r#in } => {
-                play(from_str::<Track>(read_to_string(r#in)?.as_str())?.mix()?)
+                let (json, track) = load(&r#in)?;
+                play_track(seeded(track)).map_err(|err| diagnostics::report(&r#in, &json, err))
             }
-            Command::Try { expr, track } => play(
-                {
-                    let mut custom = Track::default();
-                    if let Some(s) = track {
-                        custom.channels = vec![Channel::new(
-                            Signal(Expr::from_str(&expr)?),
-                            from_str(&format!(r#"{{"set": "aAbcCdDefFgG", "score": "{}"}}"#, s))?,
-                            442.0,
-                        )]
-                    } else {
-                        custom.channels.iter_mut().next().unwrap().signal =
-                            Signal(Expr::from_str(&expr)?);
-                    };
-                    custom
-                }
-                .mix()?,
-            ),
+            Command::Try { expr, track } => Ok(play_track({
+                let mut custom = Track::default();
+                if let Some(s) = track {
+                    custom.channels = vec![Channel::new(
+                        Signal(Expr::from_str(&expr)?),
+                        Notes::parse("aAbcCdDefFgG", &s)?,
+                        442.0,
+                    )]
+                } else {
+                    custom.channels.iter_mut().next().unwrap().signal =
+                        Signal(Expr::from_str(&expr)?);
+                };
+                seeded(custom)
+            })?),
         }
     }
 }
 
+/// Read and parse a JSON track, pointing at the faulty part of the file on error, and keep the file to point at rendering errors.
+fn load(path: &str) -> Result<(String, Track)> {
+    let json = read_to_string(path)?;
+    let track = json
+        .parse::<Track>()
+        .and_then(|track| track.check_signals().map(|()| track))
+        .map_err(|err| diagnostics::report(path, &json, err))?;
+    Ok((json, track))
+}
+
 fn main() -> Result<()> {
     Cli::look_what_to_do_and_do_it()
 }
@@ -0,0 +1,345 @@
+use anyhow::anyhow;
+use bppt::{BarError, Notes};
+use bppt_wav::{Channel, DecodeError, Error, Signal};
+use lazy_regex::regex_replace;
+use meval::{Expr, ParseError};
+use serde_json::{error::Category, Value};
+use std::str::FromStr;
+
+#[cfg(test)]
+#[path = "diagnostics/tests.rs"]
+mod tests;
+
+/// Turn a track parsing, signal binding or decoding error into a message pointing at the faulty part of the JSON file.
+pub(crate) fn report(file: &str, json: &str, err: Error) -> anyhow::Error {
+    let located = match err {
+        Error::Parse(ref inner) if inner.classify() == Category::Data => locate(json),
+        Error::Signal { .. } => locate(json),
+        Error::Decode(ref decode) => decoded(json, decode),
+        Error::Parse(_) => None,
+        _ => return err.into(),
+    }
+    .or_else(|| match err {
+        Error::Parse(ref inner) => Some((
+            regex_replace!(r" at line \d+ column \d+$", &inner.to_string(), "").to_string(),
+            offset_of(json, inner.line(), inner.column()),
+        )),
+        _ => None,
+    });
+    let Some((message, offset)) = located else {
+        return err.into();
+    };
+    anyhow!("{message}\n{}", excerpt(file, json, offset))
+}
+
+/// Find the first channel field that fails to parse, returning a message and the byte offset of the error in the file.
+fn locate(json: &str) -> Option<(String, usize)> {
+    let track: Value = serde_json::from_str(json).ok()?;
+    track
+        .get("channels")?
+        .as_array()?
+        .iter()
+        .enumerate()
+        .find_map(|(index, channel)| {
+            let path = |field| [Key::Field("channels"), Key::Index(index), Key::Field(field)];
+            if let (Some(set), Some(score)) = (
+                channel.get("set").and_then(Value::as_str),
+                channel.get("score").and_then(Value::as_str),
+            ) {
+                if let Err(err) = Notes::parse(set, score) {
+                    let start = find(json, &path("score"))?;
+                    return Some((
+                        format!("syntax error in channels[{index}].score: {err}"),
+                        inside_string(json, start, err.offset),
+                    ));
+                }
+                if let Some(err) = bar_error(channel) {
+                    let start = find(json, &path("score"))?;
+                    // uneven choices have no position, so they point at the start of the score
+                    let offset = match err {
+                        BarError::Length { offset, .. } => offset.unwrap_or(score.len()),
+                        BarError::UnevenChoice(_) => 0,
+                    };
+                    return Some((
+                        format!("bar error in channels[{index}].score: {err}"),
+                        inside_string(json, start, offset),
+                    ));
+                }
+            }
+            let signal = channel.get("signal")?;
+            let start = find(json, &path("signal"))?;
+            let Err(err) = serde_json::from_value::<Signal>(signal.clone()) else {
+                return unbound(json, index, channel, start);
+            };
+            let message = format!("invalid expression in channels[{index}].signal: {err}");
+            Some(match signal {
+                Value::String(expr) => (
+                    message,
+                    inside_string(json, start, expression_offset(expr).unwrap_or(0)),
+                ),
+                Value::Array(exprs) => {
+                    let element = exprs
+                        .iter()
+                        .rposition(|expr| expr.as_str().is_none_or(|s| !is_valid(s)))
+                        .unwrap_or(exprs.len().saturating_sub(1));
+                    let [channels, index, signal] = path("signal");
+                    (
+                        message,
+                        find(json, &[channels, index, signal, Key::Index(element)])
+                            .unwrap_or(start),
+                    )
+                }
+                _ => (message, start),
+            })
+        })
+}
+
+/// Message and byte offset in the file of an atom the decoder couldn't play, if its score was parsed from the file.
+fn decoded(json: &str, err: &DecodeError) -> Option<(String, usize)> {
+    let path = [
+        Key::Field("channels"),
+        Key::Index(err.channel),
+        Key::Field("score"),
+    ];
+    Some((
+        format!(
+            "{} in channels[{}].score ({:?})",
+            err.kind, err.channel, err.atom
+        ),
+        inside_string(json, find(json, &path)?, err.offset?),
+    ))
+}
+
+/// Locate the first unknown variable or function of a signal that parses, `start` being the offset of the signal in the file.
+fn unbound(json: &str, index: usize, channel: &Value, start: usize) -> Option<(String, usize)> {
+    let err = serde_json::from_value::<Channel>(channel.clone())
+        .ok()?
+        .check_signal()
+        .err()?;
+    let message = format!("unknown name in channels[{index}].signal: {err}");
+    let (meval::Error::UnknownVariable(name) | meval::Error::Function(name, _)) = &err else {
+        return Some((message, start));
+    };
+    Some(match channel.get("signal")? {
+        Value::String(expr) => (
+            message,
+            inside_string(json, start, identifier_offset(expr, name).unwrap_or(0)),
+        ),
+        Value::Array(exprs) => exprs
+            .iter()
+            .enumerate()
+            .find_map(|(element, expr)| {
+                // definitions bind the name on their left, so only their body can use an unknown name
+                let expr = expr.as_str()?;
+                let body = expr.split_once('=').map_or(0, |(head, _)| head.len() + 1);
+                let offset = body + identifier_offset(&expr[body..], name)?;
+                let path = [
+                    Key::Field("channels"),
+                    Key::Index(index),
+                    Key::Field("signal"),
+                    Key::Index(element),
+                ];
+                Some((
+                    message.clone(),
+                    inside_string(json, find(json, &path)?, offset),
+                ))
+            })
+            .unwrap_or((message, start)),
+        _ => (message, start),
+    })
+}
+
+/// Byte offset of the first use of `name` as a whole variable or function name in an expression.
+fn identifier_offset(expr: &str, name: &str) -> Option<usize> {
+    let part_of_name = |c: char| c.is_alphanumeric() || c == '_';
+    expr.match_indices(name)
+        .map(|(offset, _)| offset)
+        .find(|&offset| {
+            !expr[..offset].ends_with(part_of_name)
+                && !expr[offset + name.len()..].starts_with(part_of_name)
+        })
+}
+
+/// Check the bars of a channel against its time signature, deserializing its other score fields first.
+fn bar_error(channel: &Value) -> Option<BarError> {
+    let time = Notes::time_signature(channel.get("time")?.as_str()?)?;
+    let fields = channel
+        .as_object()?
+        .iter()
+        .filter(|(key, _)| ["set", "score", "scale", "seed", "dynamics"].contains(&key.as_str()))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    let mut notes: Notes = serde_json::from_value(Value::Object(fields)).ok()?;
+    notes.time = Some(time);
+    notes.check_bars().err()
+}
+
+/// Byte offset of a parse error in a signal expression.
+fn expression_offset(expr: &str) -> Option<usize> {
+    match Expr::from_str(expr) {
+        Err(meval::Error::ParseError(ParseError::UnexpectedToken(offset))) => Some(offset),
+        Err(meval::Error::ParseError(_)) => Some(expr.len()),
+        _ => None,
+    }
+}
+
+/// Whether an element of a signal array (a definition or the final expression) parses on its own.
+fn is_valid(element: &str) -> bool {
+    let body = element.split_once('=').map_or(element, |(_, body)| body);
+    Expr::from_str(body).is_ok()
+}
+
+enum Key<'a> {
+    Field(&'a str),
+    Index(usize),
+}
+
+/// Byte offset of the value at `path` in a JSON document.
+fn find(json: &str, path: &[Key]) -> Option<usize> {
+    let mut scanner = Scanner { json, pos: 0 };
+    scanner.find(path)
+}
+
+struct Scanner<'a> {
+    json: &'a str,
+    pos: usize,
+}
+
+impl Scanner<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.json.as_bytes().get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(|b| b.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Option<()> {
+        self.skip_whitespace();
+        (self.peek()? == byte).then(|| self.pos += 1)
+    }
+
+    fn find(&mut self, path: &[Key]) -> Option<usize> {
+        self.skip_whitespace();
+        let Some((key, rest)) = path.split_first() else {
+            return Some(self.pos);
+        };
+        match key {
+            Key::Field(name) => {
+                self.expect(b'{')?;
+                loop {
+                    let field = self.string()?;
+                    self.expect(b':')?;
+                    if field == *name {
+                        return self.find(rest);
+                    }
+                    self.skip_value()?;
+                    self.expect(b',')?;
+                }
+            }
+            Key::Index(index) => {
+                self.expect(b'[')?;
+                for _ in 0..*index {
+                    self.skip_value()?;
+                    self.expect(b',')?;
+                }
+                self.find(rest)
+            }
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        self.skip_whitespace();
+        let start = self.pos;
+        self.skip_string()?;
+        serde_json::from_str(&self.json[start..self.pos]).ok()
+    }
+
+    fn skip_string(&mut self) -> Option<()> {
+        self.expect(b'"')?;
+        loop {
+            match self.peek()? {
+                b'\\' => self.pos += 2,
+                b'"' => {
+                    self.pos += 1;
+                    break Some(());
+                }
+                _ => self.pos += 1,
+            }
+        }
+    }
+
+    fn skip_value(&mut self) -> Option<()> {
+        self.skip_whitespace();
+        let mut depth = 0usize;
+        loop {
+            match self.peek()? {
+                b'"' => self.skip_string()?,
+                b'{' | b'[' => {
+                    depth += 1;
+                    self.pos += 1;
+                }
+                b'}' | b']' if depth > 0 => {
+                    depth -= 1;
+                    self.pos += 1;
+                }
+                b',' | b'}' | b']' if depth == 0 => break Some(()),
+                _ => self.pos += 1,
+            }
+        }
+    }
+}
+
+/// Map a byte offset in a decoded JSON string to a byte offset in the file, `start` being the opening quote.
+fn inside_string(json: &str, start: usize, offset: usize) -> usize {
+    let mut decoded = 0;
+    let mut chars = json[start + 1..].char_indices();
+    while let Some((index, c)) = chars.next() {
+        if decoded >= offset || c == '"' {
+            return start + 1 + index;
+        }
+        decoded += match c {
+            '\\' => match chars.next() {
+                Some((_, 'u')) => {
+                    let code: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                    u32::from_str_radix(&code, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .map_or(3, char::len_utf8)
+                }
+                _ => 1,
+            },
+            c => c.len_utf8(),
+        };
+    }
+    json.len()
+}
+
+/// Byte offset of a 1-based line and column as reported by `serde_json`.
+fn offset_of(json: &str, line: usize, column: usize) -> usize {
+    let line_start = json
+        .split_inclusive('\n')
+        .take(line.saturating_sub(1))
+        .map(str::len)
+        .sum::<usize>();
+    (line_start + column.saturating_sub(1)).min(json.len())
+}
+
+/// Render the line containing `offset` with a caret under it.
+fn excerpt(file: &str, json: &str, offset: usize) -> String {
+    let line_start = json[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = json[offset..].find('\n').map_or(json.len(), |i| offset + i);
+    let line = json[..offset].matches('\n').count() + 1;
+    let column = json[line_start..offset].chars().count() + 1;
+    let gutter = " ".repeat(line.to_string().len());
+    let padding: String = json[line_start..offset]
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    format!(
+        "{gutter}--> {file}:{line}:{column}\n{gutter} |\n{line} | {}\n{gutter} | {padding}^",
+        json[line_start..line_end].trim_end_matches('\r')
+    )
+}
@@ -0,0 +1,102 @@
+use super::*;
+
+/// A track whose channels have the given score and signal, as JSON fields.
+fn track(channels: &[(&str, &str)]) -> String {
+    let channels = channels
+        .iter()
+        .map(|(score, signal)| format!(r#"{{"set": "abc", "score": {score}, "signal": {signal}}}"#))
+        .collect::<Vec<_>>()
+        .join(",\n    ");
+    format!("{{\n  \"bpm\": 120,\n  \"channels\": [\n    {channels}\n  ]\n}}")
+}
+
+#[test]
+fn scores() {
+    let json = track(&[(r#""abc""#, r#""t""#), (r#""ab z""#, r#""t""#)]);
+    let (message, offset) = locate(&json).unwrap();
+    assert!(message.contains("channels[1].score"), "{message}");
+    assert_eq!(json.find("z\"").unwrap(), offset);
+}
+
+#[test]
+fn bars() {
+    let json =
+        r#"{"channels": [{"set": "abc", "score": "abc|ab|", "time": "3/4", "signal": "t"}]}"#;
+    let (message, offset) = locate(json).unwrap();
+    assert!(
+        message.starts_with("bar error in channels[0].score"),
+        "{message}"
+    );
+    assert_eq!(json.find("|\"").unwrap(), offset, "at the bar line");
+}
+
+#[test]
+fn signals() {
+    let json = track(&[(r#""abc""#, r#""sin(2*pi*f*t))""#)]);
+    let (message, offset) = locate(&json).unwrap();
+    assert!(message.contains("channels[0].signal"), "{message}");
+    assert_eq!(json.find("))").unwrap() + 1, offset, "string");
+
+    let json = track(&[(r#""abc""#, r#"["a = 2", "b = a*", "b*t"]"#)]);
+    let (message, offset) = locate(&json).unwrap();
+    assert!(message.contains("channels[0].signal"), "{message}");
+    assert_eq!(json.find(r#""b = a*""#).unwrap(), offset, "array element");
+}
+
+#[test]
+fn unknown_names() {
+    let channel = |signal| {
+        format!(
+            r##"{{"channels": [{{"set": "abc", "score": "#g=2 abc", "tuning": 440, "params": {{"h": 1}}, "signal": {signal}}}]}}"##
+        )
+    };
+    assert_eq!(
+        None,
+        locate(&channel(r#""sin(2*pi*f*g*h*t)""#)),
+        "parameters"
+    );
+
+    let json = channel(r#""sin(2*pi*f*ts)""#);
+    let (message, offset) = locate(&json).unwrap();
+    assert!(message.contains("channels[0].signal"), "{message}");
+    assert!(message.contains("`ts`"), "{message}");
+    assert_eq!(json.find("ts)").unwrap(), offset, "string");
+
+    let json = channel(r#"["w = 2*pi*f", "x = sinc(w*t)", "x"]"#);
+    let (message, offset) = locate(&json).unwrap();
+    assert!(message.contains("`sinc`"), "{message}");
+    assert_eq!(json.find("sinc").unwrap(), offset, "array element");
+}
+
+#[test]
+fn decoding() {
+    let json = r#"{"BPM": 120, "channels": [{"set": "abc", "score": "@1 (a<)", "tuning": 440, "signal": "t"}]}"#;
+    let Err(Error::Decode(err)) = json.parse::<bppt_wav::Track>().unwrap().mix() else {
+        panic!("expected a decode error");
+    };
+    let (message, offset) = decoded(json, &err).unwrap();
+    assert!(
+        message.starts_with("octave underflow in channels[0].score"),
+        "{message}"
+    );
+    assert_eq!(json.find("<)").unwrap(), offset, "inside the loop");
+}
+
+#[test]
+fn escapes() {
+    let json = r#""a\"b\u00e9c""#;
+    assert_eq!(5, inside_string(json, 0, 3), "after a quote");
+    assert_eq!(11, inside_string(json, 0, 5), "after a unicode escape");
+    assert_eq!(12, inside_string(json, 0, 100), "closing quote");
+}
+
+#[test]
+fn excerpts() {
+    let json = "{\r\n  \"bpm\": 1x0\r\n}";
+    assert_eq!(
+        " --> track.json:2:11\n  |\n2 |   \"bpm\": 1x0\n  |           ^",
+        excerpt("track.json", json, json.find('x').unwrap()),
+        "without carriage returns"
+    );
+    assert_eq!(json.find('x').unwrap(), offset_of(json, 2, 11));
+}
@@ -0,0 +1,93 @@
+use bppt::Atom;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Result type of every fallible operation of this crate.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Everything that can go wrong between reading a track and hearing it.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The track description couldn't be parsed (invalid JSON, score syntax error...).
+    #[error("couldn't parse the track: {0}")]
+    Parse(#[from] serde_json::Error),
+    /// The signal expression of a channel couldn't be turned into a function of `t` and `f`.
+    #[error("couldn't bind the signal of channel {channel}")]
+    Signal {
+        /// Index of the channel in the track.
+        channel: usize,
+        /// Error reported by the expression evaluator.
+        source: meval::Error,
+    },
+    /// An atom of a score couldn't be decoded.
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+    /// The mix couldn't be written to a WAV file.
+    #[error("couldn't export to {path:?}")]
+    Export {
+        /// Destination of the export.
+        path: PathBuf,
+        /// Error reported by the WAV writer.
+        source: hound::Error,
+    },
+    /// A sample of the mix went beyond full scale and the export was asked not to clip it.
+    #[error("sample {position} of the mix is beyond full scale ({value})")]
+    Clipping {
+        /// Index of the first offending sample in the interleaved mix.
+        position: usize,
+        /// Value of that sample.
+        value: f32,
+    },
+    /// No audio device could be opened.
+    #[error("couldn't open an audio output: {0}")]
+    Stream(#[from] rodio::StreamError),
+    /// The audio device refused to play the mix.
+    #[error("couldn't play the mix: {0}")]
+    Playback(#[from] rodio::PlayError),
+}
+
+/// An atom the decoder couldn't turn into samples, with enough context to find it in the score.
+#[derive(PartialEq, Debug, Clone, Error)]
+#[error("{kind} in channel {channel}{} ({atom:?})", .offset.map(|offset| format!(" at byte {offset} of the score")).unwrap_or_default())]
+pub struct DecodeError {
+    /// Index of the channel in the track.
+    pub channel: usize,
+    /// Byte offset in the score of the atom the offending one comes from, if the score was parsed (see [`bppt::FlattenedNoteIterator::offset`]).
+    pub offset: Option<usize>,
+    /// The offending atom.
+    pub atom: Atom,
+    /// What went wrong.
+    pub kind: DecodeErrorKind,
+}
+
+/// The reason a [`DecodeError`] was raised.
+#[derive(PartialEq, Debug, Clone, Copy, Error)]
+pub enum DecodeErrorKind {
+    /// The octave went above 255
+    #[error("octave overflow")]
+    OctaveOverflow,
+    /// The octave went below 0
+    #[error("octave underflow")]
+    OctaveUnderflow,
+    /// The volume went above 255
+    #[error("volume overflow")]
+    VolumeOverflow,
+    /// The volume went below 0
+    #[error("volume underflow")]
+    VolumeUnderflow,
+    /// The length went above 255
+    #[error("length overflow")]
+    LengthOverflow,
+    /// The length went below 1
+    #[error("length underflow")]
+    LengthUnderflow,
+    /// The atom was added to `bppt` after this version of the backend, which doesn't know how to play it
+    #[error("unsupported atom")]
+    Unsupported,
+    /// A score parameter has the name of a built-in signal variable or constant
+    #[error("parameter name is reserved")]
+    ReservedParameter,
+    /// A container atom (loop, tuplet, degree...) reached the decoder without being flattened
+    #[error("container atom was not flattened")]
+    Unflattened,
+}
@@ -1,8 +1,11 @@
+mod error;
 mod mixing;
 mod playing;
 mod saving;
 mod structure;
 
-pub use playing::play;
-pub use saving::export;
-pub use structure::{Channel, Signal, Track};
+pub use error::{DecodeError, DecodeErrorKind, Error, Result};
+pub use mixing::{Mix, Stream};
+pub use playing::{play, play_track, Rendering};
+pub use saving::{export, export_stream, export_with, Clipping, ExportOptions, Format};
+pub use structure::{Channel, Curve, Envelope, Master, Normalisation, Phase, Signal, Track};
@@ -1,21 +1,121 @@
-use crate::structure;
-use anyhow::Result;
+use crate::{error::Result, structure};
+use std::f64::consts::FRAC_PI_2;
+use std::num::{NonZeroU16, NonZeroU32, NonZeroUsize};
 
 pub(crate) type Samples = Vec<f32>;
 
+/// A rendered track, ready to be played or exported.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Mix {
+    /// Number of output channels (2 for stereo)
+    pub outputs: NonZeroU16,
+    /// Number of samples per second, in each output
+    pub sample_rate: NonZeroU32,
+    /// Interleaved samples of every output channel
+    pub samples: Samples,
+}
+
+/// Mono samples of a track channel, with the pan changes to apply when mixing.
+pub(crate) struct Voice {
+    pub(crate) samples: Samples,
+    /// Sample offsets where the pan changes, with the new pan (-100 to 100)
+    pub(crate) pans: Vec<(usize, f64)>,
+}
+
+/// A note to render, as handed by the decoder to the channel generator.
+pub(crate) struct Note<'a> {
+    /// Number of samples to render
+    pub(crate) len: NonZeroUsize,
+    /// Number of samples of the channel before the note
+    pub(crate) onset: usize,
+    /// Index of the note in the set
+    pub(crate) n: u8,
+    pub(crate) octave: u8,
+    pub(crate) level: Level,
+    /// Current values of the score parameters
+    pub(crate) params: &'a [f64],
+}
+
+/// Volume of a note, sliding linearly from `from` to `to` while the ramp progresses from `start` by `step` per sample.
+#[derive(Clone, Copy)]
+pub(crate) struct Level {
+    from: f64,
+    to: f64,
+    start: f64,
+    step: f64,
+}
+
+impl Level {
+    pub(crate) fn constant(volume: u8) -> Self {
+        Self {
+            from: volume as f64,
+            to: volume as f64,
+            start: 0.0,
+            step: 0.0,
+        }
+    }
+
+    /// Amplitude factor of the i-th sample of the note.
+    pub(crate) fn at(&self, i: usize) -> f64 {
+        (self.from + (self.to - self.from) * (self.start + self.step * i as f64).min(1.0)) / 100.0
+    }
+}
+
+mod master;
 mod processing;
+
+pub use processing::Stream;
 #[cfg(test)]
 mod tests;
 
 impl structure::Track {
-    pub fn mix(&mut self) -> Result<Samples> {
-        let mut sorted = self.process()?;
-        sorted.sort_by(|a, b| a.len().partial_cmp(&b.len()).unwrap());
-        Ok(sorted.iter().cloned().fold(vec![], |acc, v| {
-            v.iter()
-                .zip(acc.iter().chain([0f32].iter().cycle()))
-                .map(|(s, acc)| *s / (sorted.len() as f32) + acc)
-                .collect()
-        }))
+    pub fn mix(&mut self) -> Result<Mix> {
+        let mut samples = self.process()?;
+        self.master.apply(&mut samples);
+        Ok(Mix {
+            outputs: self.outputs,
+            sample_rate: self.sample_rate,
+            samples,
+        })
+    }
+}
+
+impl Voice {
+    /// Add the voice to the interleaved samples of a mix, panned with a constant-power law, growing the mix if the voice is longer.
+    fn add_to(&self, mix: &mut Samples, outputs: NonZeroU16, gain: f32) {
+        let width = usize::from(outputs.get());
+        if mix.len() < self.samples.len() * width {
+            mix.resize(self.samples.len() * width, 0.0);
+        }
+        let mut current = gains(0.0, outputs);
+        let mut pans = self.pans.iter().peekable();
+        self.samples
+            .iter()
+            .zip(mix.chunks_exact_mut(width))
+            .enumerate()
+            .for_each(|(i, (sample, frame))| {
+                while let Some((_, pan)) = pans.next_if(|(offset, _)| *offset <= i) {
+                    current = gains(*pan, outputs);
+                }
+                frame
+                    .iter_mut()
+                    .zip(&current)
+                    .for_each(|(out, pan)| *out += sample * pan * gain);
+            });
+    }
+}
+
+/// Gain of each output for a pan from -100 (first output) to 100 (last output), keeping the power constant between the two closest outputs.
+pub(crate) fn gains(pan: f64, outputs: NonZeroU16) -> Vec<f32> {
+    let outputs = usize::from(outputs.get());
+    if outputs == 1 {
+        return vec![1.0];
     }
+    let position = (pan.clamp(-100.0, 100.0) + 100.0) / 200.0 * (outputs - 1) as f64;
+    let left = (position.floor() as usize).min(outputs - 2);
+    let angle = (position - left as f64) * FRAC_PI_2;
+    let mut gains = vec![0.0; outputs];
+    gains[left] = angle.cos() as f32;
+    gains[left + 1] = angle.sin() as f32;
+    gains
 }
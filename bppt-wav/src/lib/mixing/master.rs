@@ -0,0 +1,59 @@
+use super::*;
+
+/// Level where the limiter starts bending the samples, linear below it.
+const LIMITER_THRESHOLD: f32 = 0.9;
+
+/// Amplitude factor of a gain in dB.
+pub(crate) fn amplitude(gain: f64) -> f32 {
+    10f64.powf(gain / 20.0) as f32
+}
+
+impl structure::Master {
+    /// Apply the gain, normalisation and limiter of the master to the interleaved samples of a mix.
+    pub(super) fn apply(&self, samples: &mut [f32]) {
+        let mut factor = amplitude(self.gain);
+        if let Some(normalisation) = self.normalise {
+            let (level, target) = match normalisation {
+                structure::Normalisation::Peak(target) => (
+                    samples
+                        .iter()
+                        .fold(0f32, |peak, sample| peak.max(sample.abs())),
+                    target,
+                ),
+                structure::Normalisation::Loudness(target) => (
+                    (samples
+                        .iter()
+                        .map(|sample| f64::from(*sample).powi(2))
+                        .sum::<f64>()
+                        / samples.len().max(1) as f64)
+                        .sqrt() as f32,
+                    target,
+                ),
+            };
+            if level > 0.0 {
+                factor = amplitude(target) / level;
+            }
+        }
+        self.scale(samples, factor);
+    }
+
+    /// Multiply the samples by a factor, then apply the limiter if enabled.
+    pub(super) fn scale(&self, samples: &mut [f32], factor: f32) {
+        samples.iter_mut().for_each(|sample| {
+            *sample *= factor;
+            if self.limiter {
+                *sample = limited(*sample);
+            }
+        });
+    }
+}
+
+/// Sample bent above [`LIMITER_THRESHOLD`] so that it never goes beyond full scale, with a smooth knee.
+pub(crate) fn limited(sample: f32) -> f32 {
+    let excess = sample.abs() - LIMITER_THRESHOLD;
+    if excess <= 0.0 {
+        return sample;
+    }
+    let room = 1.0 - LIMITER_THRESHOLD;
+    sample.signum() * (LIMITER_THRESHOLD + room * (excess / room).tanh())
+}
@@ -1,45 +1,182 @@
 use super::*;
-use rayon::prelude::{IntoParallelRefMutIterator, ParallelIterator};
-use std::num::{NonZeroU16, NonZeroU8, NonZeroUsize};
+use crate::error::Error;
+use crate::structure::PAN;
+use rayon::prelude::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
+use std::num::{NonZeroU16, NonZeroU32, NonZeroU8, NonZeroUsize};
 mod decoder;
+mod stream;
+mod timeline;
+
+pub use stream::Stream;
+use timeline::Timeline;
 
 impl structure::Track {
-    pub(super) fn process(&mut self) -> Result<Vec<Samples>> {
-        self.channels
-            .par_iter_mut()
-            .map(|channel| -> Result<Samples> { channel.process(self.bpm) })
-            .collect::<Result<Vec<Samples>>>()
+    /// Render the channels a few at a time in parallel and add them to the mix in order, so that the mix doesn't depend on thread scheduling and at most one voice per thread is alive at once.
+    pub(super) fn process(&mut self) -> Result<Samples> {
+        let (bpm, sample_rate, outputs) = (self.bpm, self.sample_rate, self.outputs);
+        let batch = rayon::current_num_threads();
+        let mut mix = Samples::new();
+        for (first, channels) in self
+            .channels
+            .chunks_mut(batch)
+            .enumerate()
+            .map(|(i, channels)| (i * batch, channels))
+        {
+            channels
+                .par_iter_mut()
+                .enumerate()
+                .map(|(index, channel)| -> Result<(Voice, f32)> {
+                    Ok((
+                        channel.process(first + index, bpm, sample_rate)?,
+                        master::amplitude(channel.gain),
+                    ))
+                })
+                .collect::<Result<Vec<(Voice, f32)>>>()?
+                .iter()
+                .for_each(|(voice, gain)| voice.add_to(&mut mix, outputs, *gain));
+        }
+        Ok(mix)
     }
 }
 
 impl structure::Channel {
-    fn process(&mut self, bpm: NonZeroU16) -> Result<Samples> {
-        Decoder::new(bpm).decode(self, self.generator()?)
+    fn process(&mut self, index: usize, bpm: NonZeroU16, sample_rate: NonZeroU32) -> Result<Voice> {
+        let (mut decoder, generator) = self.renderer(index, bpm, sample_rate)?;
+        Ok(decoder.decode(self, generator)?)
+    }
+
+    /// Decoder of the score of the channel, with the generator of its notes.
+    fn renderer(
+        &self,
+        index: usize,
+        bpm: NonZeroU16,
+        sample_rate: NonZeroU32,
+    ) -> Result<(Decoder, impl Fn(Note) -> Vec<f32>)> {
+        let parameters = self.parameters();
+        let values = parameters
+            .iter()
+            .map(|name| match name.as_str() {
+                PAN => self.params.get(name).copied().unwrap_or(self.pan),
+                _ => self.params.get(name).copied().unwrap_or_default(),
+            })
+            .collect();
+        let variables = structure::RESERVED
+            .into_iter()
+            .map(String::from)
+            .chain(parameters.iter().cloned())
+            .collect::<Vec<String>>();
+        let generator = self
+            .generator(variables, sample_rate)
+            .map_err(|source| Error::Signal {
+                channel: index,
+                source,
+            })?;
+        Ok((
+            Decoder::new(index, bpm, sample_rate, parameters, values, self.pan),
+            generator,
+        ))
     }
 }
 
 struct Decoder {
+    channel: usize,
     bpm: NonZeroU16,
+    sample_rate: NonZeroU32,
     octave: u8,
     length: NonZeroU8,
     volume: u8,
+    ramp: Option<Ramp>,
+    gate: NonZeroU8,
+    articulation: Option<NonZeroU8>,
+    /// Volume of the next note only
+    accent: Option<u8>,
+    parameters: Vec<String>,
+    values: Vec<f64>,
     remainder: usize,
     tup: NonZeroUsize,
+    /// Last note, rendered once it can't be tied anymore
+    held: Option<Held>,
+    /// Pan of the channel when the score doesn't set one
+    default_pan: f64,
+    /// Number of samples rendered so far
+    rendered: usize,
+    /// Sample offsets where the pan changes, with the new pan
+    pans: Vec<(usize, f64)>,
+    /// Rendered notes, summed at their onset
+    timeline: Timeline,
+}
+
+/// A note waiting for ties before being rendered.
+struct Held {
+    n: u8,
+    octave: u8,
+    /// Number of samples of the note and its ties
+    length: usize,
+    level: Level,
+    gate: NonZeroU8,
+    values: Vec<f64>,
+    pan: f64,
+}
+
+/// A volume slide in progress.
+struct Ramp {
+    from: u8,
+    to: u8,
+    progress: Progress,
+}
+
+enum Progress {
+    Notes { total: u16, done: u16 },
+    Samples { total: usize, done: usize },
 }
 
 impl Decoder {
-    pub(super) fn new(bpm: NonZeroU16) -> Self {
+    pub(super) fn new(
+        channel: usize,
+        bpm: NonZeroU16,
+        sample_rate: NonZeroU32,
+        parameters: Vec<String>,
+        values: Vec<f64>,
+        default_pan: f64,
+    ) -> Self {
         Decoder {
+            channel,
             bpm,
+            sample_rate,
             octave: 3,
             length: NonZeroU8::new(4).unwrap(),
             volume: 100,
+            ramp: None,
+            gate: NonZeroU8::new(100).unwrap(),
+            articulation: None,
+            accent: None,
+            parameters,
+            values,
             remainder: 0,
             tup: NonZeroUsize::new(1).unwrap(),
+            held: None,
+            default_pan,
+            rendered: 0,
+            pans: vec![],
+            timeline: Timeline::default(),
         }
     }
-    fn real_length(&mut self) -> Result<usize> {
-        let numerator = 48000 * 4 * 60 + self.remainder;
+
+    /// Current pan, set by the `pan` parameter of the score.
+    fn pan(&self) -> f64 {
+        self.parameters
+            .iter()
+            .position(|parameter| parameter == PAN)
+            .map_or(self.default_pan, |index| self.values[index])
+    }
+
+    /// Number of samples in a whole note at 1 BPM.
+    fn whole_length(&self) -> usize {
+        self.sample_rate.get() as usize * 4 * 60
+    }
+
+    fn real_length(&mut self) -> usize {
+        let numerator = self.whole_length() + self.remainder;
 
         let denominator = usize::from(NonZeroUsize::from(self.bpm))
             * usize::from(NonZeroUsize::from(self.length))
@@ -47,6 +184,62 @@ impl Decoder {
 
         self.remainder = numerator % denominator;
 
-        Ok(numerator / denominator)
+        numerator / denominator
+    }
+
+    /// Number of samples in a note of the given length, regardless of tuplets.
+    fn span_length(&self, length: NonZeroU8) -> usize {
+        self.whole_length()
+            / (usize::from(NonZeroUsize::from(self.bpm)) * usize::from(NonZeroUsize::from(length)))
+    }
+
+    /// Volume reached so far, stopping the current ramp.
+    fn stop_ramp(&mut self) -> u8 {
+        if let Some(ramp) = self.ramp.take() {
+            let progress = match ramp.progress {
+                Progress::Notes { total, done } => done as f64 / total as f64,
+                Progress::Samples { total, done } => done as f64 / total as f64,
+            }
+            .min(1.0);
+            self.volume =
+                (ramp.from as f64 + (ramp.to as f64 - ramp.from as f64) * progress).round() as u8;
+        }
+        self.volume
+    }
+
+    /// Volume of the next `len` samples, moving the current ramp forward.
+    fn level(&mut self, len: usize) -> Level {
+        let Some(ramp) = &mut self.ramp else {
+            return Level::constant(self.volume);
+        };
+        let (start, step, done) = match &mut ramp.progress {
+            Progress::Notes { total, done } => {
+                *done += 1;
+                (
+                    (*done - 1) as f64 / *total as f64,
+                    1.0 / (*total as f64 * len.max(1) as f64),
+                    done == total,
+                )
+            }
+            Progress::Samples { total, done } => {
+                *done += len;
+                (
+                    (*done - len) as f64 / *total as f64,
+                    1.0 / *total as f64,
+                    done >= total,
+                )
+            }
+        };
+        let level = Level {
+            from: ramp.from as f64,
+            to: ramp.to as f64,
+            start,
+            step,
+        };
+        if done {
+            self.volume = ramp.to;
+            self.ramp = None;
+        }
+        level
     }
 }
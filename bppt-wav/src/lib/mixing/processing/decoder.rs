@@ -1,74 +1,210 @@
-use super::Decoder;
+use super::{Decoder, Held, Progress, Ramp};
+use crate::error::{DecodeError, DecodeErrorKind};
+use crate::mixing::{Level, Note, Voice};
 use crate::structure;
-use anyhow::{Context, Result};
-use bppt::Atom;
+use bppt::{Atom, Span};
 use std::num::{NonZeroU8, NonZeroUsize};
 
 impl Decoder {
     pub(super) fn decode(
         &mut self,
         channel: &structure::Channel,
-        gen: impl Fn(NonZeroUsize, u8, u8, u8) -> Vec<f32>,
-    ) -> Result<Vec<f32>> {
-        Ok(channel
-            .notes
-            .flat_iter()
-            .map(|atom| {
-                match atom {
-                    Atom::O(o) => self.octave = u8::from(o) - 1,
-                    Atom::L(l) => {
-                        self.length = l;
-                    }
-                    Atom::V(v) => self.volume = v,
-                    Atom::N(n, tup) => {
-                        self.tup = tup;
-                        let length = self.real_length()?;
-                        if length != 0 {
-                            return Ok(Some(gen(
-                                NonZeroUsize::new(length).unwrap(),
-                                n,
-                                self.octave,
-                                self.volume,
-                            )));
+        gen: impl Fn(Note) -> Vec<f32>,
+    ) -> Result<Voice, DecodeError> {
+        let mut atoms = channel.notes.flat_iter();
+        let mut samples = vec![];
+        while let Some(atom) = atoms.next() {
+            let rendered = self.step(&atom, &gen).map_err(|kind| DecodeError {
+                channel: self.channel,
+                offset: atoms.offset(),
+                atom,
+                kind,
+            })?;
+            samples.extend(rendered.into_iter().flatten());
+        }
+        samples.extend(self.finish(&gen));
+        Ok(Voice {
+            samples,
+            pans: std::mem::take(&mut self.pans),
+        })
+    }
+
+    /// Render the held note, if any, placing it at the current position and moving past its slot.
+    fn release(&mut self, gen: impl Fn(Note) -> Vec<f32>) {
+        let Some(held) = self.held.take() else {
+            return;
+        };
+        if self.pans.last().is_none_or(|(_, pan)| *pan != held.pan) {
+            self.pans.push((self.rendered, held.pan));
+        }
+        let sounding = held.length * usize::from(u8::from(held.gate)) / 100;
+        if let Some(sounding) = NonZeroUsize::new(sounding) {
+            self.timeline.add(
+                self.rendered,
+                &gen(Note {
+                    len: sounding,
+                    onset: self.rendered,
+                    n: held.n,
+                    octave: held.octave,
+                    level: held.level,
+                    params: &held.values,
+                }),
+            );
+        }
+        self.rendered += held.length;
+    }
+
+    /// Render the held note and take every sample left, once the score is over.
+    pub(super) fn finish(&mut self, gen: impl Fn(Note) -> Vec<f32>) -> Vec<f32> {
+        self.release(gen);
+        self.rendered = self.rendered.max(self.timeline.end());
+        self.timeline.take(self.rendered)
+    }
+
+    /// Read an atom, returning the samples before the new position once no later note can overlap them.
+    pub(super) fn step(
+        &mut self,
+        atom: &Atom,
+        gen: impl Fn(Note) -> Vec<f32>,
+    ) -> Result<Option<Vec<f32>>, DecodeErrorKind> {
+        self.read(atom, gen)?;
+        let samples = self.timeline.take(self.rendered);
+        Ok((!samples.is_empty()).then_some(samples))
+    }
+
+    fn read(&mut self, atom: &Atom, gen: impl Fn(Note) -> Vec<f32>) -> Result<(), DecodeErrorKind> {
+        match atom {
+            Atom::O(o) => self.octave = u8::from(*o) - 1,
+            Atom::L(l) => {
+                self.length = *l;
+            }
+            Atom::V(v) => {
+                self.stop_ramp();
+                self.volume = *v;
+            }
+            Atom::N(n, tup) => {
+                self.release(gen);
+                self.tup = *tup;
+                let length = self.real_length();
+                self.held = Some(Held {
+                    n: *n,
+                    octave: self.octave,
+                    length,
+                    level: match self.accent.take() {
+                        Some(accent) => {
+                            self.level(length);
+                            Level::constant(accent)
                         }
+                        None => self.level(length),
+                    },
+                    gate: self.articulation.take().unwrap_or(self.gate),
+                    values: self.values.clone(),
+                    pan: self.pan(),
+                });
+            }
+            Atom::Rest(tup) => {
+                self.release(gen);
+                self.tup = *tup;
+                let length = self.real_length();
+                self.level(length);
+                self.rendered += length;
+            }
+            Atom::OIncr => {
+                self.octave = self
+                    .octave
+                    .checked_add(1)
+                    .ok_or(DecodeErrorKind::OctaveOverflow)?
+            }
+            Atom::ODecr => {
+                self.octave = self
+                    .octave
+                    .checked_sub(1)
+                    .ok_or(DecodeErrorKind::OctaveUnderflow)?
+            }
+            Atom::VIncr => {
+                self.volume = self
+                    .stop_ramp()
+                    .checked_add(1)
+                    .ok_or(DecodeErrorKind::VolumeOverflow)?
+            }
+            Atom::VDecr => {
+                self.volume = self
+                    .stop_ramp()
+                    .checked_sub(1)
+                    .ok_or(DecodeErrorKind::VolumeUnderflow)?
+            }
+            Atom::LIncr => {
+                self.length = self
+                    .length
+                    .checked_mul(NonZeroU8::new(2).unwrap())
+                    .ok_or(DecodeErrorKind::LengthOverflow)?;
+            }
+            Atom::LDecr => {
+                self.length = NonZeroU8::new(u8::from(self.length) / NonZeroU8::new(2).unwrap())
+                    .ok_or(DecodeErrorKind::LengthUnderflow)?;
+            }
+            Atom::Loop(_, _)
+            | Atom::Tuplet(_)
+            | Atom::Degree(_)
+            | Atom::Chance(_, _)
+            | Atom::Choice(_)
+            | Atom::Grace(_, _)
+            | Atom::Trill(_, _)
+            | Atom::Mordent(_, _)
+            | Atom::Arpeggio(_, _, _)
+            | Atom::Dynamic(_)
+            | Atom::Interval(_) => return Err(DecodeErrorKind::Unflattened),
+            Atom::More => {
+                let length = self.real_length();
+                if let Some(Ramp {
+                    to,
+                    progress: Progress::Samples { total, done },
+                    ..
+                }) = &mut self.ramp
+                {
+                    *done += length;
+                    if done >= total {
+                        self.volume = *to;
+                        self.ramp = None;
                     }
-                    Atom::Rest(tup) => {
-                        self.tup = tup;
-                        return Ok(Some(vec![0f32; self.real_length()?]));
-                    }
-                    Atom::OIncr => self.octave += 1,
-                    Atom::ODecr => self.octave -= 1,
-                    Atom::VIncr => self.volume += 1,
-                    Atom::VDecr => self.volume -= 1,
-                    Atom::LIncr => {
-                        self.length = self
-                            .length
-                            .checked_mul(NonZeroU8::new(2).unwrap())
-                            .with_context(|| {
-                                format!("L overflow, already at length {}", self.length)
-                            })?;
-                    }
-                    Atom::LDecr => {
-                        self.length =
-                            NonZeroU8::new(u8::from(self.length) / NonZeroU8::new(2).unwrap())
-                                .with_context(|| {
-                                    format!("L underflow, already at length {}", self.length)
-                                })?;
-                    }
-                    Atom::Loop(_, _) | Atom::Tuplet(_) => unreachable!(
-                        "Loops and tuplets should be flattened by the FlattenedNoteIterator"
-                    ),
-                    Atom::More => {
-                        unimplemented!("Sorry, '+' operator is unimplemented at the moment")
-                    }
+                }
+                match &mut self.held {
+                    Some(held) => held.length += length,
+                    None => self.rendered += length,
+                }
+            }
+            Atom::Mark(_) | Atom::Bar(_) => {}
+            Atom::G(g) => self.gate = *g,
+            Atom::Articulation(g) => self.articulation = Some(*g),
+            Atom::Accent(v) => self.accent = Some(*v),
+            Atom::P(name, value) => {
+                let index = self
+                    .parameters
+                    .iter()
+                    .position(|parameter| parameter == name)
+                    .ok_or(DecodeErrorKind::ReservedParameter)?;
+                self.values[index] = *value;
+            }
+            Atom::VRamp(to, span) => {
+                let from = self.stop_ramp();
+                let progress = match span {
+                    Span::Notes(n) => Progress::Notes {
+                        total: u16::from(*n),
+                        done: 0,
+                    },
+                    Span::Length(l) => Progress::Samples {
+                        total: self.span_length(*l).max(1),
+                        done: 0,
+                    },
                 };
-                Ok(None)
-            })
-            .collect::<Result<Vec<Option<Vec<f32>>>>>()?
-            .iter()
-            .flatten()
-            .flatten()
-            .cloned()
-            .collect())
+                self.ramp = Some(Ramp {
+                    from,
+                    to: *to,
+                    progress,
+                });
+            }
+            _ => return Err(DecodeErrorKind::Unsupported),
+        };
+        Ok(())
     }
 }
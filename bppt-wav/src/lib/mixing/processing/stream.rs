@@ -0,0 +1,151 @@
+use super::*;
+use crate::error::DecodeError;
+use bppt::FlattenedNoteIterator;
+use rayon::prelude::IntoParallelRefMutIterator;
+use std::collections::VecDeque;
+
+/// Number of frames (one sample per output) in each block of a [`Stream`].
+const BLOCK: usize = 4096;
+
+/// A track rendered block by block as its scores are read, instead of all at once.
+///
+/// Each item is a block of interleaved samples of every output, the last one being shorter. The channels render their part of each block in parallel, then are added in order.
+/// The master gain and limiter are applied, but not the normalisation, which needs the whole mix.
+pub struct Stream<'a> {
+    outputs: NonZeroU16,
+    master: structure::Master,
+    channels: Vec<ChannelStream<'a>>,
+}
+
+/// The rendering state of a track channel.
+struct ChannelStream<'a> {
+    index: usize,
+    atoms: FlattenedNoteIterator<'a>,
+    decoder: Decoder,
+    generator: Box<dyn Fn(Note) -> Vec<f32> + Send>,
+    gain: f32,
+    /// Samples rendered but not mixed yet
+    pending: VecDeque<f32>,
+    /// Pan changes not reached yet, at sample offsets from the start of the channel
+    pans: VecDeque<(usize, f64)>,
+    /// Gains of the current pan on each output
+    current: Vec<f32>,
+    /// Number of samples mixed so far
+    mixed: usize,
+    /// Whether the whole score was read
+    done: bool,
+}
+
+impl structure::Track {
+    /// Render the track incrementally, see [`Stream`].
+    pub fn stream(&self) -> Result<Stream<'_>> {
+        Ok(Stream {
+            outputs: self.outputs,
+            master: self.master,
+            channels: self
+                .channels
+                .iter()
+                .enumerate()
+                .map(|(index, channel)| {
+                    let (decoder, generator) =
+                        channel.renderer(index, self.bpm, self.sample_rate)?;
+                    Ok(ChannelStream {
+                        index,
+                        atoms: channel.notes.flat_iter(),
+                        decoder,
+                        generator: Box::new(generator),
+                        gain: master::amplitude(channel.gain),
+                        pending: VecDeque::new(),
+                        pans: VecDeque::new(),
+                        current: gains(0.0, self.outputs),
+                        mixed: 0,
+                        done: false,
+                    })
+                })
+                .collect::<Result<Vec<ChannelStream>>>()?,
+        })
+    }
+}
+
+impl Iterator for Stream<'_> {
+    type Item = Result<Samples>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // errors are collected in channel order, so that the first channel failing is the one reported
+        if let Err(err) = self
+            .channels
+            .par_iter_mut()
+            .map(|channel| channel.fill(BLOCK))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .collect::<std::result::Result<(), DecodeError>>()
+        {
+            self.channels.clear();
+            return Some(Err(err.into()));
+        }
+        let frames = self
+            .channels
+            .iter()
+            .map(|channel| channel.pending.len().min(BLOCK))
+            .max()
+            .filter(|frames| *frames > 0)?;
+        let mut block = vec![0f32; frames * usize::from(self.outputs.get())];
+        self.channels
+            .iter_mut()
+            .for_each(|channel| channel.add_to(&mut block, self.outputs));
+        self.master
+            .scale(&mut block, master::amplitude(self.master.gain));
+        Some(Ok(block))
+    }
+}
+
+impl ChannelStream<'_> {
+    /// Read the score until at least `frames` samples are pending or the score is over.
+    fn fill(&mut self, frames: usize) -> std::result::Result<(), DecodeError> {
+        while !self.done && self.pending.len() < frames {
+            let samples = match self.atoms.next() {
+                Some(atom) => {
+                    self.decoder
+                        .step(&atom, &self.generator)
+                        .map_err(|kind| DecodeError {
+                            channel: self.index,
+                            offset: self.atoms.offset(),
+                            atom,
+                            kind,
+                        })?
+                }
+                None => {
+                    self.done = true;
+                    Some(self.decoder.finish(&self.generator))
+                }
+            };
+            self.pending.extend(samples.into_iter().flatten());
+            self.pans.extend(self.decoder.pans.drain(..));
+        }
+        Ok(())
+    }
+
+    /// Add the pending samples to the start of an interleaved block, as many as fit.
+    fn add_to(&mut self, block: &mut [f32], outputs: NonZeroU16) {
+        let width = usize::from(outputs.get());
+        let frames = self.pending.len().min(block.len() / width);
+        self.pending
+            .drain(..frames)
+            .zip(block.chunks_exact_mut(width))
+            .for_each(|(sample, frame)| {
+                while let Some((_, pan)) = self
+                    .pans
+                    .front()
+                    .filter(|(offset, _)| *offset <= self.mixed)
+                {
+                    self.current = gains(*pan, outputs);
+                    self.pans.pop_front();
+                }
+                frame
+                    .iter_mut()
+                    .zip(&self.current)
+                    .for_each(|(out, pan)| *out += sample * pan * self.gain);
+                self.mixed += 1;
+            });
+    }
+}
@@ -0,0 +1,40 @@
+use std::collections::VecDeque;
+
+/// Samples of a channel being rendered, where each note is summed at its onset so that it can ring over the next ones.
+#[derive(Default)]
+pub(super) struct Timeline {
+    /// Samples from `start` on, the ones after the decoder position still waiting for overlapping notes
+    samples: VecDeque<f32>,
+    /// Offset of the first sample kept, every sample before it being taken already
+    start: usize,
+}
+
+impl Timeline {
+    /// Sum a note into the timeline from its onset, which can't come before the samples taken already.
+    pub(super) fn add(&mut self, onset: usize, note: &[f32]) {
+        let offset = onset - self.start;
+        let end = offset + note.len();
+        if self.samples.len() < end {
+            self.samples.resize(end, 0f32);
+        }
+        self.samples
+            .range_mut(offset..end)
+            .zip(note)
+            .for_each(|(sample, note)| *sample += note);
+    }
+
+    /// Take the samples before `end` out of the timeline, silent where no note was placed.
+    pub(super) fn take(&mut self, end: usize) -> Vec<f32> {
+        let count = end - self.start;
+        if self.samples.len() < count {
+            self.samples.resize(count, 0f32);
+        }
+        self.start = end;
+        self.samples.drain(..count).collect()
+    }
+
+    /// Offset of the end of the last note placed, or of the samples taken if later.
+    pub(super) fn end(&self) -> usize {
+        self.start + self.samples.len()
+    }
+}
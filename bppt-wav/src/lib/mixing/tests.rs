@@ -1,7 +1,17 @@
-use crate::{mixing::tests::helpers::custom_mask, structure::Track};
+use crate::mixing::{gains, master};
+use crate::{
+    error::{DecodeError, DecodeErrorKind, Error},
+    mixing::tests::helpers::{
+        custom_mask, custom_mask_at, levels, mask_error, rendered, signal_track,
+    },
+    playing::Rendering,
+    structure::{Curve, Envelope, Phase, Track},
+};
+use bppt::Atom;
 use serde_json::from_str;
+use std::num::{NonZeroU16, NonZeroU32};
 mod helpers;
-use helpers::mixed_root;
+use helpers::{mixed_root, mixed_root_at};
 
 #[test]
 fn note_length() {
@@ -30,6 +40,7 @@ fn note_loss() {
 #[test]
 fn loop_precision() {
     assert_eq!(48_000, custom_mask("[abc]"), "just a lil triplet");
+    assert_eq!(48_000, custom_mask("[*a>b<c]"), "a triplet with commands");
     assert_eq!(48_000, custom_mask("[(12a)]"), "a dodecaplet");
     assert_eq!(
         48_000,
@@ -37,3 +48,476 @@ fn loop_precision() {
         "a fucking triakosiadodecaplet"
     );
 }
+
+#[test]
+fn sample_rates() {
+    for rate in [22_050, 44_100, 96_000] {
+        let scaled = |samples: usize| samples * rate as usize / 48_000;
+        assert_eq!(
+            scaled(48_000),
+            mixed_root_at(rate, 60, (4, 4)),
+            "{rate} Hz quarters"
+        );
+        assert_eq!(
+            scaled(24_000),
+            mixed_root_at(rate, 120, (4, 4)),
+            "{rate} Hz at 120 bpm"
+        );
+        assert_eq!(
+            scaled(96_000),
+            mixed_root_at(rate, 60, (2, 8)),
+            "{rate} Hz note loss"
+        );
+        assert_eq!(
+            scaled(48_000),
+            custom_mask_at(rate, "[abc]"),
+            "{rate} Hz triplet"
+        );
+        assert_eq!(
+            scaled(48_000),
+            custom_mask_at(rate, "[(312a)]"),
+            "{rate} Hz big tuplet"
+        );
+    }
+    let mut track = signal_track("t", "a");
+    assert_eq!(
+        NonZeroU32::new(48_000).unwrap(),
+        track.sample_rate,
+        "default rate"
+    );
+    track.sample_rate = NonZeroU32::new(44_100).unwrap();
+    let samples = track.mix().unwrap().samples;
+    assert_eq!(44_100, samples.len(), "one second");
+    assert!(
+        (samples[44_099] - 1.0).abs() < 1e-4,
+        "time base follows the rate"
+    );
+}
+
+#[test]
+fn decode_errors() {
+    assert_eq!(
+        DecodeError {
+            channel: 0,
+            offset: Some(3),
+            atom: Atom::ODecr,
+            kind: DecodeErrorKind::OctaveUnderflow
+        },
+        mask_error("@1a<"),
+        "octave underflow"
+    );
+    assert_eq!(
+        Some(5),
+        mask_error("@1 (a<)").offset,
+        "offset of the atom in the score, inside a loop"
+    );
+    assert_eq!(
+        DecodeErrorKind::VolumeOverflow,
+        mask_error("!255^").kind,
+        "volume overflow"
+    );
+    assert_eq!(
+        DecodeErrorKind::VolumeUnderflow,
+        mask_error("!0_").kind,
+        "volume underflow"
+    );
+    assert_eq!(
+        DecodeErrorKind::LengthUnderflow,
+        mask_error("$1'").kind,
+        "length underflow"
+    );
+}
+
+#[test]
+fn signal_error() {
+    let mut track = Track::default();
+    track.channels[0].signal = from_str(r#""sin(2*pi*g*t)""#).unwrap();
+    assert!(matches!(track.mix(), Err(Error::Signal { channel: 0, .. })));
+    track.channels[0].signal = from_str(r#""g(2*pi*f*t)""#).unwrap();
+    assert!(
+        matches!(track.mix(), Err(Error::Signal { channel: 0, .. })),
+        "unknown function"
+    );
+}
+
+#[test]
+fn parse_error() {
+    assert!(matches!(
+        r#"{"BPM": 0, "channels": []}"#.parse::<Track>(),
+        Err(Error::Parse(_))
+    ));
+}
+
+#[test]
+fn ramps() {
+    let samples = levels("!0 ~!100:2 aa a");
+    assert_eq!(144_000, samples.len(), "ramps don't change lengths");
+    assert!(samples[0].abs() < 1e-3, "starts from the current volume");
+    assert!(
+        (samples[48_000] - 0.5).abs() < 1e-3,
+        "halfway after one note"
+    );
+    assert!((samples[95_999] - 1.0).abs() < 1e-3, "reaches the target");
+    assert!(samples[96_000..].iter().all(|s| *s == 1.0), "stays there");
+
+    let samples = levels("!100 ~!0$2 aaa");
+    assert!((samples[24_000] - 0.75).abs() < 1e-3, "fixed duration");
+    assert!((samples[72_000] - 0.25).abs() < 1e-3, "across notes");
+    assert!(samples[96_000..].iter().all(|s| *s == 0.0), "then silence");
+
+    let samples = levels("!0 ~!100$1 a++++ ^ a");
+    assert!(
+        samples[240_000..].iter().all(|s| (*s - 1.01).abs() < 1e-6),
+        "ties end the ramp at its target"
+    );
+}
+
+#[test]
+fn articulation() {
+    let samples = levels("%50 a");
+    assert_eq!(48_000, samples.len(), "gates don't change lengths");
+    assert!(samples[..24_000].iter().all(|s| *s == 1.0), "sounding part");
+    assert!(samples[24_000..].iter().all(|s| *s == 0.0), "silent part");
+
+    let samples = levels("%25 *a =a a");
+    assert!(samples[23_999] == 1.0 && samples[24_000] == 0.0, "staccato");
+    assert!(samples[48_000..96_000].iter().all(|s| *s == 1.0), "legato");
+    assert!(samples[108_000] == 0.0, "only for the next note");
+}
+
+#[test]
+fn parameters() {
+    let samples = rendered("duty", "#duty=0.5 a #duty=0.25 a");
+    assert_eq!(0.5, samples[0], "set in the score");
+    assert_eq!(0.25, samples[48_000], "changed in the score");
+
+    let mut track = signal_track("gain", "a #gain=1 a");
+    track.channels[0].params.insert("gain".to_string(), 0.5);
+    let samples = track.mix().unwrap().samples;
+    assert_eq!(0.5, samples[0], "default values");
+    assert_eq!(1.0, samples[48_000], "overridden defaults");
+
+    assert_eq!(
+        DecodeErrorKind::ReservedParameter,
+        mask_error("#t=1 a").kind,
+        "reserved names"
+    );
+}
+
+#[test]
+fn degrees() {
+    let mut track = from_str::<Track>(
+        r#"{
+            "BPM": 60,
+            "outputs": 1,
+            "master": { "limiter": false },
+            "channels": [
+                {
+                    "signal": "sin(2*pi*f*t)",
+                    "set": "aAbcCdDefFgG",
+                    "scale": "cdefgab",
+                    "score": "@4 &1&3[&5&8]&7 &9",
+                    "tuning": 442
+                }
+            ]
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(
+        rendered("sin(2*pi*f*t)", "@4 ce[g>c<]>b< >d<"),
+        track.mix().unwrap().samples,
+        "degrees play the notes of the scale"
+    );
+}
+
+#[test]
+fn bars() {
+    assert_eq!(
+        levels("ab c"),
+        levels("ab| c|"),
+        "bar lines don't change the rendering"
+    );
+}
+
+#[test]
+fn euclidean_rhythms() {
+    assert_eq!(levels("$8 a..a..a."), levels("$8 {3,8:a}"));
+    assert_eq!(
+        levels("$8 ..[ab]..[ab].[ab]"),
+        levels("$8 {3,8,1:ab}"),
+        "rotated phrases"
+    );
+}
+
+#[test]
+fn ties() {
+    let signal = "sin(2*pi*f*t)";
+    assert_eq!(rendered(signal, "$2 a"), rendered(signal, "a+"), "one note");
+    assert_eq!(
+        rendered(signal, "a..b"),
+        rendered(signal, "a.+b"),
+        "tied rests"
+    );
+    let samples = levels("%50 a+");
+    assert!(
+        samples[..48_000].iter().all(|s| *s == 1.0),
+        "gate over the tie"
+    );
+    assert!(samples[48_000..].iter().all(|s| *s == 0.0));
+}
+
+#[test]
+fn ornaments() {
+    for ornament in [",b c", ",(ab)c", "~cd", "-cb"] {
+        assert_eq!(48_000, custom_mask(ornament), "{ornament} lasts one note");
+    }
+    let signal = "sin(2*pi*f*t)";
+    assert_eq!(
+        rendered(signal, "$16 b")[..],
+        rendered(signal, ",b c")[..12_000],
+        "grace notes come first"
+    );
+}
+
+#[test]
+fn arpeggios() {
+    assert_eq!(48_000, custom_mask("{><6:ceg}"), "one slot");
+    assert_eq!(
+        960_000,
+        custom_mask("(20{>7:ceg})"),
+        "no drift over many steps"
+    );
+    assert_eq!(custom_mask("a"), custom_mask("?0{>3:ceg}"), "silenced");
+}
+
+#[test]
+fn dynamics() {
+    let samples = levels("!mf a !sfz a a");
+    assert_eq!(0.65, samples[0], "levels");
+    assert_eq!(0.95, samples[48_000], "accents");
+    assert_eq!(0.65, samples[96_000], "only for the next note");
+}
+
+#[test]
+fn panning() {
+    let mut track = signal_track("1", "a #pan=-100 a #pan=100 a");
+    track.outputs = NonZeroU16::new(2).unwrap();
+    track.channels[0].pan = 50.0;
+    let samples = track.mix().unwrap().samples;
+    assert_eq!(2 * 144_000, samples.len(), "interleaved");
+    let power = |i: usize| samples[2 * i].powi(2) + samples[2 * i + 1].powi(2);
+    assert!(samples[0] < samples[1], "channel pan");
+    assert!((power(0) - 1.0).abs() < 1e-6, "constant power");
+    assert_eq!((1.0, 0.0), (samples[96_000], samples[96_001]), "left");
+    assert!(
+        samples[192_000].abs() < 1e-6 && samples[192_001] == 1.0,
+        "right"
+    );
+
+    assert_eq!(vec![1.0], gains(30.0, NonZeroU16::new(1).unwrap()), "mono");
+    let gains = gains(0.0, NonZeroU16::new(3).unwrap());
+    assert_eq!(1.0, gains[1], "centre speaker");
+}
+
+#[test]
+fn mixer() {
+    let track = |master: &str| {
+        from_str::<Track>(&format!(
+            r#"{{
+                "BPM": 60,
+                "outputs": 1,
+                "master": {master},
+                "channels": [
+                    {{ "signal": "1", "set": "a", "score": "!50a", "tuning": 442 }},
+                    {{ "signal": "1", "set": "a", "score": "!50a", "tuning": 442, "gain": -6 }}
+                ]
+            }}"#
+        ))
+        .unwrap()
+        .mix()
+        .unwrap()
+        .samples
+    };
+    let quiet = master::amplitude(-6.0);
+    assert!(
+        (0.5 + 0.5 * quiet - track("{}")[0]).abs() < 1e-6,
+        "channel gains"
+    );
+    assert!(
+        (0.5 * (1.0 + quiet) * 2.0 - track(r#"{ "gain": 6.0206, "limiter": false }"#)[0]).abs()
+            < 1e-3,
+        "master gain"
+    );
+    let peak = track(r#"{ "normalise": { "peak": -6.0206 } }"#)[0];
+    assert!((peak - 0.5).abs() < 1e-4, "peak normalisation");
+    let loud = track(r#"{ "normalise": { "loudness": -3 }, "limiter": false }"#)[0];
+    assert!(
+        (loud - master::amplitude(-3.0)).abs() < 1e-4,
+        "loudness normalisation ({loud})"
+    );
+    let limited = track(r#"{ "gain": 6 }"#)[0];
+    assert!(0.9 < limited && limited < 1.0, "limited ({limited})");
+    assert_eq!(0.5, master::limited(0.5), "transparent below the threshold");
+    assert!(master::limited(-10.0) >= -1.0, "never beyond full scale");
+}
+
+#[test]
+fn streaming() {
+    let json = r#"{
+        "BPM": 120,
+        "master": { "gain": 3 },
+        "channels": [
+            { "signal": "sin(2*pi*f*t)", "set": "aAbcCdDefFgG", "score": "a #pan=-50 b ^ [cde] .", "tuning": 442, "pan": 20 },
+            { "signal": "1", "set": "a", "score": "!80 ` a a", "tuning": 442, "gain": -3 }
+        ]
+    }"#;
+    let mut track = from_str::<Track>(json).unwrap();
+    let blocks = track
+        .stream()
+        .unwrap()
+        .collect::<crate::Result<Vec<Vec<f32>>>>()
+        .unwrap();
+    assert!(blocks.len() > 1, "several blocks");
+    assert!(
+        blocks
+            .iter()
+            .rev()
+            .skip(1)
+            .all(|block| block.len() == blocks[0].len()),
+        "equal blocks but the last one"
+    );
+    let mix = track.mix().unwrap();
+    assert_eq!(mix.samples, blocks.concat(), "same samples as the mix");
+    let (rendering, worker) = Rendering::spawn(from_str::<Track>(json).unwrap());
+    assert_eq!(mix.samples, rendering.collect::<Vec<f32>>(), "as a source");
+    worker.join().unwrap().unwrap();
+
+    let (rendering, worker) = Rendering::spawn(signal_track("1", "a@1a<"));
+    assert!(rendering.count() <= 48_000, "the source ends on errors");
+    assert!(
+        matches!(worker.join().unwrap(), Err(Error::Decode(err)) if err.kind == DecodeErrorKind::OctaveUnderflow),
+        "the error is reported by the thread"
+    );
+}
+
+#[test]
+fn envelopes() {
+    let shaped = |envelope: Envelope, mask: &str| {
+        let mut track = signal_track("1", mask);
+        track.channels[0].envelope = envelope;
+        track.mix().unwrap().samples
+    };
+    let close = |a: f32, b: f32| (a - b).abs() < 1e-3;
+    let adsr = Envelope {
+        attack: 0.5,
+        decay: 0.25,
+        sustain: 0.5,
+        release: 0.5,
+        curve: Curve::Linear,
+    };
+    let samples = shaped(adsr, "a .");
+    assert_eq!(96_000, samples.len(), "the release rings over the rest");
+    assert!(close(0.5, samples[12_000]), "attack");
+    assert!(close(0.75, samples[30_000]), "decay");
+    assert!(close(0.5, samples[40_000]), "sustain");
+    assert!(close(0.25, samples[60_000]), "release");
+    assert_eq!(0.0, samples[80_000], "released");
+    assert_eq!(
+        72_000,
+        shaped(adsr, "a").len(),
+        "the release extends the end"
+    );
+    let overlapped = shaped(adsr, "aa");
+    assert!(
+        close(0.5 + 0.25, overlapped[48_000 + 12_000]),
+        "the release overlaps the next note"
+    );
+
+    let attack = |curve| {
+        shaped(
+            Envelope {
+                attack: 1.0,
+                curve,
+                ..Default::default()
+            },
+            "a",
+        )[24_000]
+    };
+    assert!(close(0.5, attack(Curve::Linear)), "linear");
+    assert!(attack(Curve::Exponential) < 0.5, "exponential");
+    assert!(attack(Curve::Logarithmic) > 0.5, "logarithmic");
+}
+
+#[test]
+fn overlap_add() {
+    let mut track = signal_track("1", "aaa");
+    track.channels[0].envelope = Envelope {
+        release: 2.0,
+        ..Default::default()
+    };
+    let streamed = track
+        .stream()
+        .unwrap()
+        .collect::<crate::Result<Vec<Vec<f32>>>>()
+        .unwrap()
+        .concat();
+    let samples = track.mix().unwrap().samples;
+    assert_eq!(240_000, samples.len(), "the last release extends the track");
+    assert!(
+        (2.0 - samples[120_000]).abs() < 1e-3,
+        "three notes sounding at once"
+    );
+    assert_eq!(samples, streamed, "the stream places notes the same way");
+}
+
+#[test]
+fn continuous_phase() {
+    let jump = |phase| {
+        let mut track = signal_track("sin(2*pi*f*t)", "bb");
+        track.channels[0].phase = phase;
+        let samples = track.mix().unwrap().samples;
+        samples
+            .windows(2)
+            .map(|pair| (pair[1] - pair[0]).abs())
+            .fold(0f32, f32::max)
+    };
+    // the largest step of a 248 Hz sine
+    let smooth = 2.0 * std::f32::consts::PI * 248.1 / 48_000.0;
+    assert!(jump(Phase::Reset) > 2.0 * smooth, "click between notes");
+    assert!(jump(Phase::Continuous) < smooth, "no click");
+    assert_eq!(
+        rendered("sin(2*pi*f*t)", "b"),
+        {
+            let mut track = signal_track("sin(2*pi*f*t)", "b");
+            track.channels[0].phase = Phase::Continuous;
+            track.mix().unwrap().samples
+        },
+        "same first note"
+    );
+}
+
+#[test]
+fn signal_variables() {
+    let progress = rendered("p", "a");
+    assert_eq!(0.5, progress[23_999], "progress");
+    assert_eq!(1.0, progress[47_999], "end of the note");
+    assert_eq!(2.0, rendered("d", "$2a")[0], "duration");
+    assert_eq!(
+        0.25,
+        rendered("v", "!50a")[0],
+        "velocity, applied once more"
+    );
+    let time = rendered("T", "a a");
+    assert!((1.0 - time[48_000]).abs() < 1e-4, "time in the track");
+    assert_eq!(53.0, rendered("n+10*o", "@5c")[0], "note and octave");
+    assert_eq!(
+        DecodeErrorKind::ReservedParameter,
+        mask_error("#T=1 a").kind,
+        "new reserved names"
+    );
+    assert_eq!(
+        DecodeErrorKind::ReservedParameter,
+        mask_error("#pi=3 a").kind,
+        "constants"
+    );
+}
@@ -1,10 +1,17 @@
 use super::*;
 
 pub(super) fn mixed_root(bpm: u8, lengths: (u8, u8)) -> usize {
+    mixed_root_at(48_000, bpm, lengths)
+}
+
+pub(super) fn mixed_root_at(sample_rate: u32, bpm: u8, lengths: (u8, u8)) -> usize {
     from_str::<Track>(
         format!(
             r#"{{
                     "BPM": {},
+                    "outputs": 1,
+                    "master": {{ "limiter": false }},
+                    "sample_rate": {},
                     "channels": [
                         {{
                             "signal": "4*abs(f*t-floor(f*t+1/2))-1",
@@ -20,36 +27,66 @@ pub(super) fn mixed_root(bpm: u8, lengths: (u8, u8)) -> usize {
                         }}
                     ]
             }}"#,
-            bpm, lengths.0, lengths.1
+            bpm, sample_rate, lengths.0, lengths.1
         )
         .as_str(),
     )
     .unwrap()
     .mix()
     .unwrap()
+    .samples
     .len()
 }
 
 pub(super) fn custom_mask(mask: &str) -> usize {
+    custom_track(mask).mix().unwrap().samples.len()
+}
+
+pub(super) fn custom_mask_at(sample_rate: u32, mask: &str) -> usize {
+    let mut track = custom_track(mask);
+    track.sample_rate = NonZeroU32::new(sample_rate).unwrap();
+    track.mix().unwrap().samples.len()
+}
+
+pub(super) fn mask_error(mask: &str) -> DecodeError {
+    match custom_track(mask).mix().unwrap_err() {
+        Error::Decode(err) => err,
+        err => panic!("expected a decode error, got {err}"),
+    }
+}
+
+/// Samples of a single channel playing a constant signal, so that they only reflect the volume.
+pub(super) fn levels(mask: &str) -> Vec<f32> {
+    rendered("1", mask)
+}
+
+pub(super) fn rendered(signal: &str, mask: &str) -> Vec<f32> {
+    signal_track(signal, mask).mix().unwrap().samples
+}
+
+fn custom_track(mask: &str) -> Track {
+    signal_track("4*abs(f*t-floor(f*t+1/2))-1", mask)
+}
+
+pub(super) fn signal_track(signal: &str, mask: &str) -> Track {
     from_str::<Track>(
         format!(
             r#"{{
                     "BPM": 60,
+                    "outputs": 1,
+                    "master": {{ "limiter": false }},
                     "channels": [
                         {{
-                            "signal": "4*abs(f*t-floor(f*t+1/2))-1",
+                            "signal": "{}",
                             "set": "aAbcCdDefFgG",
                             "score": "{}",
                             "tuning": 442
                         }}
                     ]
             }}"#,
-            mask
+            signal, mask
         )
         .as_str(),
     )
     .unwrap()
-    .mix()
-    .unwrap()
-    .len()
 }
@@ -1,13 +1,108 @@
-use crate::{mixing::Samples, structure::SAMPLE_RATE};
-use anyhow::Result;
-use rodio::{buffer::SamplesBuffer, OutputStream, Source};
+use crate::{
+    error::Result,
+    mixing::{Mix, Samples},
+    structure::Track,
+};
+use rodio::{buffer::SamplesBuffer, OutputStream, Sink, Source};
+use std::{
+    num::{NonZeroU16, NonZeroU32},
+    sync::mpsc::{sync_channel, Receiver},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// Number of rendered blocks waiting to be played, ahead of the one playing.
+const AHEAD: usize = 4;
 
 /// Play an entire album, printing the name of each track as it plays. Uses [`rodio`](https://docs.rs/rodio) for the playback.
-pub fn play(mix: Samples) -> Result<()> {
-    let (_stream, stream_handle) = OutputStream::try_default().unwrap();
-    let source = SamplesBuffer::new(1, SAMPLE_RATE, mix);
-    let duration = source.total_duration().unwrap();
+pub fn play(mix: Mix) -> Result<()> {
+    let (_stream, stream_handle) = OutputStream::try_default()?;
+    let source = SamplesBuffer::new(mix.outputs.get(), mix.sample_rate.get(), mix.samples);
+    let duration = source.total_duration().unwrap_or_default();
     stream_handle.play_raw(source)?;
     std::thread::sleep(duration);
     Ok(())
 }
+
+/// Play a track while it renders, so that the playback starts right away.
+///
+/// A track normalising its master needs the whole mix, so it is then mixed before being played like with [`play`].
+pub fn play_track(mut track: Track) -> Result<()> {
+    if track.master.normalise.is_some() {
+        return play(track.mix()?);
+    }
+    let (_stream, stream_handle) = OutputStream::try_default()?;
+    let sink = Sink::try_new(&stream_handle)?;
+    let (rendering, worker) = Rendering::spawn(track);
+    sink.append(rendering);
+    sink.sleep_until_end();
+    worker
+        .join()
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
+
+/// A track rendered on its own thread (see [`Stream`](crate::Stream)) as it is played, usable as a [`rodio::Source`].
+pub struct Rendering {
+    outputs: NonZeroU16,
+    sample_rate: NonZeroU32,
+    blocks: Receiver<Samples>,
+    block: std::vec::IntoIter<f32>,
+}
+
+impl Rendering {
+    /// Start rendering a track, returning the source and the rendering thread.
+    ///
+    /// The source ends early if the rendering fails, the thread then returning the error. The rendering stops when the source is dropped.
+    pub fn spawn(track: Track) -> (Self, JoinHandle<Result<()>>) {
+        let (sender, blocks) = sync_channel(AHEAD);
+        let (outputs, sample_rate) = (track.outputs, track.sample_rate);
+        let worker = thread::spawn(move || {
+            for block in track.stream()? {
+                if sender.send(block?).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        });
+        (
+            Self {
+                outputs,
+                sample_rate,
+                blocks,
+                block: vec![].into_iter(),
+            },
+            worker,
+        )
+    }
+}
+
+impl Iterator for Rendering {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        loop {
+            if let Some(sample) = self.block.next() {
+                return Some(sample);
+            }
+            self.block = self.blocks.recv().ok()?.into_iter();
+        }
+    }
+}
+
+impl Source for Rendering {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.outputs.get()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate.get()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
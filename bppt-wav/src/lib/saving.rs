@@ -1,19 +1,254 @@
-use crate::{mixing::Samples, structure::SAMPLE_RATE};
-use anyhow::{Ok, Result};
-use hound::{SampleFormat, WavSpec};
-use std::path::Path;
-
-pub fn export<P: AsRef<Path>>(mix: Samples, path: P) -> Result<()> {
-    let spec = WavSpec {
-        channels: 1,
-        sample_rate: SAMPLE_RATE,
-        bits_per_sample: 16,
-        sample_format: SampleFormat::Int,
-    };
-    let filename = path;
-    let mut writer = hound::WavWriter::create(filename, spec)?;
-    mix.iter()
-        .map(|sample| (sample * (i16::MAX as f32)) as i16)
-        .try_for_each(|sample| writer.write_sample(sample))?;
-    Ok(())
+use crate::{
+    error::{Error, Result},
+    mixing::Mix,
+    structure::Track,
+};
+use hound::{SampleFormat, WavSpec, WavWriter};
+use std::{
+    fs::File,
+    io::BufWriter,
+    num::{NonZeroU16, NonZeroU32},
+    path::Path,
+    str::FromStr,
+};
+
+#[cfg(test)]
+mod tests;
+
+/// Sample format of an exported WAV file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// 8-bit integer samples
+    Int8,
+    /// 16-bit integer samples (CD quality)
+    #[default]
+    Int16,
+    /// 24-bit integer samples
+    Int24,
+    /// 32-bit integer samples
+    Int32,
+    /// 32-bit floating point samples, written as they are mixed
+    Float32,
+}
+
+/// What to do with a mix that goes beyond full scale (samples outside of -1 to 1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Clipping {
+    /// Hard clip the samples to full scale
+    #[default]
+    Clip,
+    /// Refuse to export the mix
+    Error,
+    /// Scale the whole mix down so that its loudest sample is at full scale
+    Normalise,
+}
+
+/// Settings of a WAV export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExportOptions {
+    /// Sample format of the file
+    pub format: Format,
+    /// Add triangular (TPDF) noise of one least significant bit before quantizing to integers
+    pub dither: bool,
+    /// Handling of samples beyond full scale
+    pub clipping: Clipping,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            format: Format::default(),
+            dither: true,
+            clipping: Clipping::default(),
+        }
+    }
+}
+
+impl Format {
+    fn bits(self) -> u16 {
+        match self {
+            Format::Int8 => 8,
+            Format::Int16 => 16,
+            Format::Int24 => 24,
+            Format::Int32 | Format::Float32 => 32,
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "8" => Ok(Format::Int8),
+            "16" => Ok(Format::Int16),
+            "24" => Ok(Format::Int24),
+            "32" => Ok(Format::Int32),
+            "f32" | "float" => Ok(Format::Float32),
+            _ => Err(format!(
+                "unknown sample format {s:?} (expected 8, 16, 24, 32 or f32)"
+            )),
+        }
+    }
+}
+
+impl FromStr for Clipping {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "clip" => Ok(Clipping::Clip),
+            "error" => Ok(Clipping::Error),
+            "normalise" | "normalize" => Ok(Clipping::Normalise),
+            _ => Err(format!(
+                "unknown clipping policy {s:?} (expected clip, error or normalise)"
+            )),
+        }
+    }
+}
+
+/// Write a mix to a dithered 16-bit WAV file with one channel per output of the mix, clipping samples beyond full scale.
+pub fn export<P: AsRef<Path>>(mix: Mix, path: P) -> Result<()> {
+    export_with(mix, path, ExportOptions::default())
+}
+
+/// Write a mix to a WAV file with the given sample format, dithering and clipping policy.
+pub fn export_with<P: AsRef<Path>>(mix: Mix, path: P, options: ExportOptions) -> Result<()> {
+    let mut writer = Writer::create(path.as_ref(), mix.outputs, mix.sample_rate, options)?;
+    writer.write(mix.samples)?;
+    writer.finalize()
+}
+
+/// Render a track to a WAV file block by block (see [`Stream`](crate::Stream)), without holding the whole mix in memory.
+///
+/// Normalising (in the master of the track or as the clipping policy) needs the whole mix, so the track is then mixed at once like with [`export_with`].
+pub fn export_stream<P: AsRef<Path>>(
+    track: &mut Track,
+    path: P,
+    options: ExportOptions,
+) -> Result<()> {
+    if options.clipping == Clipping::Normalise || track.master.normalise.is_some() {
+        return export_with(track.mix()?, path, options);
+    }
+    let mut writer = Writer::create(path.as_ref(), track.outputs, track.sample_rate, options)?;
+    track.stream()?.try_for_each(|block| writer.write(block?))?;
+    writer.finalize()
+}
+
+/// A WAV file being written.
+struct Writer<'a> {
+    path: &'a Path,
+    wav: WavWriter<BufWriter<File>>,
+    options: ExportOptions,
+    /// Source of the dithering noise
+    rng: Option<fastrand::Rng>,
+    /// Number of samples written so far
+    written: usize,
+}
+
+impl<'a> Writer<'a> {
+    fn create(
+        path: &'a Path,
+        outputs: NonZeroU16,
+        sample_rate: NonZeroU32,
+        options: ExportOptions,
+    ) -> Result<Self> {
+        let spec = WavSpec {
+            channels: outputs.get(),
+            sample_rate: sample_rate.get(),
+            bits_per_sample: options.format.bits(),
+            sample_format: match options.format {
+                Format::Float32 => SampleFormat::Float,
+                _ => SampleFormat::Int,
+            },
+        };
+        Ok(Self {
+            path,
+            wav: WavWriter::create(path, spec).map_err(|source| Error::Export {
+                path: path.to_path_buf(),
+                source,
+            })?,
+            options,
+            rng: options.dither.then(|| fastrand::Rng::with_seed(0)),
+            written: 0,
+        })
+    }
+
+    fn error(&self, source: hound::Error) -> Error {
+        Error::Export {
+            path: self.path.to_path_buf(),
+            source,
+        }
+    }
+
+    /// Append samples to the file, applying the clipping policy to them alone.
+    fn write(&mut self, samples: Vec<f32>) -> Result<()> {
+        let samples = clipped(samples, self.options.clipping).map_err(|err| match err {
+            Error::Clipping { position, value } => Error::Clipping {
+                position: position + self.written,
+                value,
+            },
+            err => err,
+        })?;
+        self.written += samples.len();
+        let bits = self.options.format.bits();
+        samples
+            .into_iter()
+            .try_for_each(|sample| match self.options.format {
+                Format::Float32 => self.wav.write_sample(sample),
+                Format::Int8 => self
+                    .wav
+                    .write_sample(quantized(sample, bits, self.rng.as_mut()) as i8),
+                Format::Int16 => self
+                    .wav
+                    .write_sample(quantized(sample, bits, self.rng.as_mut()) as i16),
+                Format::Int24 | Format::Int32 => {
+                    self.wav
+                        .write_sample(quantized(sample, bits, self.rng.as_mut()) as i32)
+                }
+            })
+            .map_err(|source| self.error(source))
+    }
+
+    fn finalize(self) -> Result<()> {
+        let path = self.path;
+        self.wav.finalize().map_err(|source| Error::Export {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+}
+
+/// Apply a clipping policy to the samples of a mix.
+fn clipped(mut samples: Vec<f32>, clipping: Clipping) -> Result<Vec<f32>> {
+    let peak = samples
+        .iter()
+        .fold(0f32, |peak, sample| peak.max(sample.abs()));
+    if peak <= 1.0 {
+        return Ok(samples);
+    }
+    match clipping {
+        Clipping::Clip => samples
+            .iter_mut()
+            .for_each(|sample| *sample = sample.clamp(-1.0, 1.0)),
+        Clipping::Error => {
+            let position = samples
+                .iter()
+                .position(|sample| sample.abs() > 1.0)
+                .unwrap();
+            return Err(Error::Clipping {
+                position,
+                value: samples[position],
+            });
+        }
+        Clipping::Normalise => samples.iter_mut().for_each(|sample| *sample /= peak),
+    }
+    Ok(samples)
+}
+
+/// Integer value of a sample at the given bit depth, optionally dithered.
+fn quantized(sample: f32, bits: u16, rng: Option<&mut fastrand::Rng>) -> i64 {
+    let max = ((1i64 << (bits - 1)) - 1) as f64;
+    let noise = rng.map_or(0.0, |rng| rng.f64() - rng.f64());
+    ((f64::from(sample) * max + noise).round() as i64).clamp(-max as i64 - 1, max as i64)
 }
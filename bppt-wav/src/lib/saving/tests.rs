@@ -0,0 +1,136 @@
+use super::*;
+use std::num::{NonZeroU16, NonZeroU32};
+
+fn mix(samples: Vec<f32>) -> Mix {
+    Mix {
+        outputs: NonZeroU16::new(1).unwrap(),
+        sample_rate: NonZeroU32::new(48_000).unwrap(),
+        samples,
+    }
+}
+
+/// Export a mix to a temporary file and read back its spec and integer samples.
+fn round_trip(name: &str, samples: Vec<f32>, options: ExportOptions) -> (WavSpec, Vec<i32>) {
+    let path = std::env::temp_dir().join(format!("bppt-wav-{}-{name}.wav", std::process::id()));
+    export_with(mix(samples), &path, options).unwrap();
+    let mut reader = hound::WavReader::open(&path).unwrap();
+    let spec = reader.spec();
+    let samples = match spec.sample_format {
+        SampleFormat::Int => reader.samples::<i32>().map(|s| s.unwrap()).collect(),
+        SampleFormat::Float => reader
+            .samples::<f32>()
+            .map(|s| (s.unwrap() * 1000.0) as i32)
+            .collect(),
+    };
+    std::fs::remove_file(path).unwrap();
+    (spec, samples)
+}
+
+#[test]
+fn formats() {
+    let plain = |format| ExportOptions {
+        format,
+        dither: false,
+        ..Default::default()
+    };
+    let samples = vec![0.0, 0.5, -1.0, 1.0];
+    let (spec, read) = round_trip("8", samples.clone(), plain(Format::Int8));
+    assert_eq!(
+        (8, SampleFormat::Int),
+        (spec.bits_per_sample, spec.sample_format)
+    );
+    assert_eq!(vec![0, 64, -127, 127], read, "8-bit");
+    let (_, read) = round_trip("16", samples.clone(), plain(Format::Int16));
+    assert_eq!(vec![0, 16384, -32767, 32767], read, "16-bit");
+    let (spec, read) = round_trip("24", samples.clone(), plain(Format::Int24));
+    assert_eq!(24, spec.bits_per_sample);
+    assert_eq!(vec![0, 4_194_304, -8_388_607, 8_388_607], read, "24-bit");
+    let (_, read) = round_trip("32", samples.clone(), plain(Format::Int32));
+    assert_eq!(
+        vec![0, 1_073_741_824, -2_147_483_647, 2_147_483_647],
+        read,
+        "32-bit"
+    );
+    let (spec, read) = round_trip("f32", vec![0.25, 1.5], plain(Format::Float32));
+    assert_eq!(
+        (32, SampleFormat::Float),
+        (spec.bits_per_sample, spec.sample_format)
+    );
+    assert_eq!(vec![250, 1000], read, "float, clipped");
+    assert_eq!(Ok(Format::Int24), "24".parse());
+    assert!("12".parse::<Format>().is_err());
+}
+
+#[test]
+fn dithering() {
+    let mut rng = fastrand::Rng::with_seed(0);
+    let dithered = (0..10_000)
+        .map(|_| quantized(0.3 / 127.0, 8, Some(&mut rng)))
+        .collect::<Vec<i64>>();
+    assert!(
+        dithered.iter().all(|value| (-1..=2).contains(value)),
+        "at most one LSB of noise"
+    );
+    let mean = dithered.iter().sum::<i64>() as f64 / dithered.len() as f64;
+    assert!((mean - 0.3).abs() < 0.05, "unbiased ({mean})");
+    assert_eq!(0, quantized(0.3 / 127.0, 8, None), "rounded without dither");
+}
+
+#[test]
+fn clipping() {
+    let loud = vec![0.5, 2.0, -1.5];
+    assert_eq!(
+        vec![0.5, 1.0, -1.0],
+        clipped(loud.clone(), Clipping::Clip).unwrap()
+    );
+    assert_eq!(
+        vec![0.25, 1.0, -0.75],
+        clipped(loud.clone(), Clipping::Normalise).unwrap()
+    );
+    match clipped(loud, Clipping::Error) {
+        Err(Error::Clipping { position, value }) => assert_eq!((1, 2.0), (position, value)),
+        other => panic!("expected a clipping error, got {other:?}"),
+    }
+    let quiet = vec![0.5, -0.25];
+    assert_eq!(
+        quiet,
+        clipped(quiet.clone(), Clipping::Normalise).unwrap(),
+        "untouched"
+    );
+}
+
+#[test]
+fn streamed_export() {
+    let json = r#"{
+        "BPM": 240,
+        "channels": [
+            { "signal": "sin(2*pi*f*t)", "set": "aAbcCdDefFgG", "score": "a #pan=-50 b ^ [cde] .", "tuning": 442 },
+            { "signal": "2", "set": "a", "score": ". a", "tuning": 442 }
+        ]
+    }"#;
+    let path = |name: &str| {
+        std::env::temp_dir().join(format!("bppt-wav-{}-{name}.wav", std::process::id()))
+    };
+    let mut track = json.parse::<Track>().unwrap();
+    export(track.mix().unwrap(), path("mixed")).unwrap();
+    export_stream(&mut track, path("streamed"), ExportOptions::default()).unwrap();
+    assert_eq!(
+        std::fs::read(path("mixed")).unwrap(),
+        std::fs::read(path("streamed")).unwrap(),
+        "same file"
+    );
+    let strict = ExportOptions {
+        clipping: Clipping::Error,
+        ..Default::default()
+    };
+    track.master.limiter = false;
+    match export_stream(&mut track, path("strict"), strict) {
+        Err(Error::Clipping { position, .. }) => {
+            assert_eq!(24_000, position, "position in the whole mix")
+        }
+        other => panic!("expected a clipping error, got {other:?}"),
+    }
+    ["mixed", "streamed", "strict"]
+        .into_iter()
+        .for_each(|name| std::fs::remove_file(path(name)).unwrap());
+}
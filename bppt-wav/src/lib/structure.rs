@@ -1,40 +1,221 @@
 pub use self::de::Signal;
-use anyhow::{Ok, Result};
-use bppt::Notes;
+pub use self::envelope::{Curve, Envelope};
+use crate::error::{Error, Result};
+use crate::mixing::Note;
+use bppt::{Atom, Notes};
 use derive_new::new;
 use serde::Deserialize;
+use std::cell::Cell;
+use std::collections::HashMap;
 use std::fmt::Debug;
-use std::num::{NonZeroU16, NonZeroUsize};
+use std::num::{NonZeroU16, NonZeroU32};
+use std::str::FromStr;
 
 mod de;
 mod default;
+mod envelope;
 
+/// Sample rate of tracks that don't set one.
 pub(crate) const SAMPLE_RATE: u32 = 48000;
+/// Variables of the signal expression that score parameters can't override: time in the note (s), frequency (Hz), progress through the note (0 to 1, beyond during the release), duration of the note (s), velocity (volume, 1 at `!100`), time in the track (s), index of the note in the set and octave (as written in the score, `@4` being 4).
+pub(crate) const RESERVED: [&str; 8] = ["t", "f", "p", "d", "v", "T", "n", "o"];
+/// Constants built into meval, which score parameters can't shadow either.
+pub(crate) const CONSTANTS: [&str; 2] = ["pi", "e"];
+/// Score parameter moving the channel between the outputs, from -100 to 100.
+pub(crate) const PAN: &str = "pan";
+
+thread_local! {
+    /// Constants and functions built into meval, once per thread since meval contexts can't be sent between threads.
+    static BUILTINS: meval::Context<'static> = meval::Context::new();
+}
+
+/// Signal variables and their values for the current sample, looked up by name without building a context.
+struct Variables<'a> {
+    names: &'a [String],
+    values: &'a [f64],
+}
+
+impl meval::ContextProvider for Variables<'_> {
+    fn get_var(&self, name: &str) -> Option<f64> {
+        self.names
+            .iter()
+            .position(|variable| variable == name)
+            .map(|index| self.values[index])
+    }
+}
+
+/// Check that `expr` only uses the given variables and meval's built-ins.
+fn bind(expr: &meval::Expr, variables: &[String]) -> std::result::Result<(), meval::Error> {
+    // unknown variables and functions are the only evaluation errors, so evaluating the signal once checks it
+    let zeros = vec![0.0; variables.len()];
+    BUILTINS.with(|builtins| {
+        expr.eval_with_context((
+            Variables {
+                names: variables,
+                values: &zeros,
+            },
+            builtins,
+        ))
+    })?;
+    Ok(())
+}
 
 #[derive(new, PartialEq, Debug, Deserialize)]
 pub struct Track {
     #[serde(rename = "BPM")]
     pub bpm: NonZeroU16,
     pub channels: Vec<Channel>,
+    /// Number of output channels of the mix (2 for stereo)
+    #[serde(default = "Track::stereo")]
+    #[new(value = "Track::stereo()")]
+    pub outputs: NonZeroU16,
+    /// Number of samples per second of the mix, in each output
+    #[serde(default = "Track::default_sample_rate")]
+    #[new(value = "Track::default_sample_rate()")]
+    pub sample_rate: NonZeroU32,
+    /// Processing of the sum of the channels
+    #[serde(default)]
+    #[new(default)]
+    pub master: Master,
+}
+
+/// Gain, normalisation and limiting of the summed channels of a track.
+#[derive(new, PartialEq, Debug, Deserialize, Clone, Copy)]
+pub struct Master {
+    /// Gain of the mix in dB, applied before the normalisation
+    #[serde(default)]
+    #[new(default)]
+    pub gain: f64,
+    /// Level the mix is scaled to, if any
+    #[serde(default)]
+    #[new(default)]
+    pub normalise: Option<Normalisation>,
+    /// Softly bend the loudest samples so that the mix never goes beyond full scale
+    #[serde(default = "Master::limited")]
+    #[new(value = "Master::limited()")]
+    pub limiter: bool,
+}
+
+/// Target level of a mix normalisation, in dB relative to full scale.
+#[derive(PartialEq, Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Normalisation {
+    /// Loudest sample of the mix
+    Peak(f64),
+    /// Root mean square of the whole mix
+    Loudness(f64),
+}
+
+impl FromStr for Track {
+    type Err = Error;
+
+    /// Parse a track from its JSON description.
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(serde_json::from_str(s)?)
+    }
+}
+
+impl Track {
+    /// Check the signal of every channel (see [`Channel::check_signal`]) without rendering anything.
+    pub fn check_signals(&self) -> Result<()> {
+        self.channels
+            .iter()
+            .enumerate()
+            .try_for_each(|(index, channel)| {
+                channel.check_signal().map_err(|source| Error::Signal {
+                    channel: index,
+                    source,
+                })
+            })
+    }
 }
 
 impl Channel {
-    pub(crate) fn generator(&self) -> Result<impl Fn(NonZeroUsize, u8, u8, u8) -> Vec<f32>> {
-        let func = self.signal.clone().0.bind2("t", "f")?;
+    /// Names of the parameters set in the score or given a default value, sorted.
+    pub(crate) fn parameters(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .params
+            .keys()
+            .cloned()
+            .chain(self.notes.flat_iter().filter_map(|atom| match atom {
+                Atom::P(name, _) => Some(name),
+                _ => None,
+            }))
+            .filter(|name| {
+                !RESERVED.contains(&name.as_str()) && !CONSTANTS.contains(&name.as_str())
+            })
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Check that the signal only uses the [`RESERVED`] variables, the parameters of the channel and meval's constants and functions, as when the channel is rendered.
+    pub fn check_signal(&self) -> std::result::Result<(), meval::Error> {
+        let variables = RESERVED
+            .into_iter()
+            .map(String::from)
+            .chain(self.parameters())
+            .collect::<Vec<String>>();
+        bind(&self.signal.0, &variables)
+    }
+
+    /// Bind the signal to its `variables` ([`RESERVED`] ones first, then the parameters). Notes are shaped by the envelope of the channel and rendered with their release.
+    pub(crate) fn generator(
+        &self,
+        variables: Vec<String>,
+        sample_rate: NonZeroU32,
+    ) -> std::result::Result<impl Fn(Note) -> Vec<f32>, meval::Error> {
+        let expr = self.signal.clone().0;
+        bind(&expr, &variables)?;
+        let func = move |values: &[f64]| {
+            BUILTINS
+                .with(|builtins| {
+                    expr.eval_with_context((
+                        Variables {
+                            names: &variables,
+                            values,
+                        },
+                        builtins,
+                    ))
+                })
+                .unwrap_or_default()
+        };
         let notes = self.notes.set;
         let tuning = self.tuning;
-        Ok(
-            move |len: NonZeroUsize, n: u8, octave: u8, volume: u8| -> Vec<f32> {
-                let f = (tuning as f64 / 16f64)
-                    * 2.0_f64.powf(((notes * octave + n) as f64) / (notes as f64));
-                (1..=usize::from(len))
-                    .map(|i| {
-                        let t = (i as f64) / (SAMPLE_RATE as f64);
-                        (func(t, f) * ((volume as f64) / 100f64)) as f32
-                    })
-                    .collect()
-            },
-        )
+        let envelope = self.envelope;
+        let phase = self.phase;
+        let rate = f64::from(sample_rate.get());
+        // cycles of the signal played by the previous notes, modulo 1
+        let cycles = Cell::new(0f64);
+        Ok(move |note: Note| -> Vec<f32> {
+            let f = (tuning as f64 / 16f64)
+                * 2.0_f64
+                    .powf((notes as f64 * note.octave as f64 + note.n as f64) / (notes as f64));
+            let length = usize::from(note.len);
+            let duration = length as f64 / rate;
+            let mut values = [0.0, f, 0.0, duration, 0.0, 0.0]
+                .into_iter()
+                .chain([f64::from(note.n), f64::from(note.octave) + 1.0])
+                .chain(note.params.iter().copied())
+                .collect::<Vec<f64>>();
+            let start = cycles.get();
+            let time = |i: usize| match phase {
+                Phase::Reset => (i as f64) / rate,
+                Phase::Continuous => (start + (i as f64) * f / rate) / f,
+            };
+            cycles.set((time(length) * f).fract());
+            (1..=length + envelope.release_length(rate))
+                .map(|i| {
+                    let level = note.level.at(i - 1);
+                    values[0] = time(i);
+                    values[2] = i as f64 / length as f64;
+                    values[4] = level;
+                    values[5] = (note.onset + i) as f64 / rate;
+                    (func(&values) * level * envelope.at(i - 1, length, rate)) as f32
+                })
+                .collect()
+        })
     }
 }
 
@@ -44,4 +225,35 @@ pub struct Channel {
     #[serde(flatten)]
     pub notes: Notes,
     pub tuning: f32,
+    /// Default values of the score parameters (`#name=value`), available as variables in the signal (0 if not given).
+    #[serde(default)]
+    #[new(default)]
+    pub params: HashMap<String, f64>,
+    /// Position of the channel between the outputs, from -100 (left) to 100 (right), until the score sets the `pan` parameter.
+    #[serde(default)]
+    #[new(default)]
+    pub pan: f64,
+    /// Gain of the channel in the mix, in dB.
+    #[serde(default)]
+    #[new(default)]
+    pub gain: f64,
+    /// Amplitude envelope of every note.
+    #[serde(default)]
+    #[new(default)]
+    pub envelope: Envelope,
+    /// How the time of the signal goes from a note to the next.
+    #[serde(default)]
+    #[new(default)]
+    pub phase: Phase,
+}
+
+/// Time base of the signal of a channel.
+#[derive(PartialEq, Eq, Debug, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    /// `t` starts again at every note
+    #[default]
+    Reset,
+    /// `t` follows the phase of the notes, so that `f*t` goes on from a note to the next without jumping
+    Continuous,
 }
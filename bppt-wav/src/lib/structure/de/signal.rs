@@ -1,9 +1,10 @@
-use lazy_regex::{regex_captures, regex_replace_all};
+use definitions::substitute;
 use meval::Expr;
 use serde::de::Error;
 use serde::Deserialize;
 use std::str::FromStr;
 
+mod definitions;
 #[cfg(test)]
 mod tests;
 
@@ -36,55 +37,22 @@ impl<'de> Deserialize<'de> for Signal {
                     exprs.push(s);
                 }
 
-                let mut result_expr = None;
+                let (expr, definitions) = exprs
+                    .split_last()
+                    .ok_or_else(|| A::Error::custom("no expressions found"))?;
 
-                for context in exprs.iter().rev() {
-                    let (_, left, right) = regex_captures!(
-                        r"^(?P<left>[A-z|(|)]+) *= *(?P<right>.+)$"s,
-                        context.as_str()
-                    )
-                    .ok_or_else(|| {
-                        A::Error::custom(format!(r#"invalid expression: "{}""#, context))
-                    })?;
+                let expr =
+                    definitions
+                        .iter()
+                        .rev()
+                        .try_fold(expr.clone(), |expr, definition| {
+                            substitute(&expr, definition).ok_or_else(|| {
+                                A::Error::custom(format!(r#"invalid definition: "{definition}""#))
+                            })
+                        })?;
 
-                    let expr = match left.contains('(') {
-                        true => {
-                            let fname = regex_captures!(r"^[A-z]+"s, left).ok_or_else(|| {
-                                A::Error::custom(format!(r#"invalid function name: "{left}""#))
-                            })?;
-
-                            regex_replace_all!(
-                                r"(?P<f>[A-z]+)\((?P<arg>[^()]+)\)"s,
-                                &exprs.join(";"),
-                                move |_, f, arg: &str| {
-                                    if f == fname {
-                                        return arg.to_string();
-                                    }
-                                    format!("{f}({arg})")
-                                }
-                            )
-                            .to_string()
-                        }
-                        false => regex_replace_all!(
-                            r"\b[A-z]+\b"s,
-                            &exprs.join(";"),
-                            move |word: &str| {
-                                if word == left {
-                                    return format!("({right})");
-                                }
-                                word.to_string()
-                            }
-                        )
-                        .to_string(),
-                    };
-
-                    result_expr = Some(expr);
-                }
-
-                let expr = result_expr.ok_or_else(|| A::Error::custom("no expressions found"))?;
                 Ok(Signal(
-                    Expr::from_str(expr.as_str())
-                        .map_err(|err| A::Error::custom(err.to_string()))?,
+                    Expr::from_str(&expr).map_err(|err| A::Error::custom(err.to_string()))?,
                 ))
             }
         }
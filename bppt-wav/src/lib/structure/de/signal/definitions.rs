@@ -0,0 +1,34 @@
+use lazy_regex::{regex_captures, regex_replace_all};
+
+/// Replace every use of the variable or function defined by `definition` (`a = 2` or `f(x) = 2*x`) in `expr`.
+pub(super) fn substitute(expr: &str, definition: &str) -> Option<String> {
+    let (_, name, param, body) = regex_captures!(
+        r"^ *(?P<name>[A-Za-z_]\w*) *(?:\( *(?P<param>[A-Za-z_]\w*) *\))? *= *(?P<body>.+)$"s,
+        definition
+    )?;
+    Some(match param.is_empty() {
+        true => replace_variable(expr, name, body),
+        false => regex_replace_all!(
+            r"\b(?P<f>[A-Za-z_]\w*)\((?P<arg>[^()]*)\)"s,
+            expr,
+            |call: &str, f: &str, arg: &str| match f == name {
+                true => format!("({})", replace_variable(body, param, arg)),
+                false => call.to_string(),
+            }
+        )
+        .to_string(),
+    })
+}
+
+/// Replace every occurrence of the variable `name` in `expr` with `(value)`, leaving function calls alone.
+fn replace_variable(expr: &str, name: &str, value: &str) -> String {
+    regex_replace_all!(
+        r"\b(?P<word>[A-Za-z_]\w*)\b(?P<call>\(?)"s,
+        expr,
+        |word: &str, candidate: &str, call: &str| match candidate == name && call.is_empty() {
+            true => format!("({value})"),
+            false => word.to_string(),
+        }
+    )
+    .to_string()
+}
@@ -9,31 +9,43 @@ fn string() {
     );
 }
 
+/// Values of a signal of `f` and `t` at a few points, so that signals written differently can be compared.
+fn values(signal: Signal) -> Vec<f64> {
+    let func = signal.0.bind2("f", "t").unwrap();
+    [(440.0, 0.0), (440.0, 0.3), (27.5, 1.7)]
+        .into_iter()
+        .map(|(f, t)| func(f, t))
+        .collect()
+}
+
 #[test]
 fn array() {
+    let expected = values(Signal(Expr::from_str("sin(2*pi*f*t)").unwrap()));
+    let array = |json| values(from_str(json).unwrap());
+    assert_eq!(expected, array(r#"["sin(2*pi*f*t)"]"#), "single element");
     assert_eq!(
-        Signal(Expr::from_str("sin(2*pi*f*t)").unwrap()),
-        from_str(r#"["sin(2*pi*f*t)"]"#).unwrap(),
-        "single element"
-    );
-    assert_eq!(
-        Signal(Expr::from_str("sin(2*pi*f*t)").unwrap()),
-        from_str(r#"["a = 2", "sin(a*pi*f*t)"]"#).unwrap(),
+        expected,
+        array(r#"["a = 2", "sin(a*pi*f*t)"]"#),
         "1 variable"
     );
     assert_eq!(
-        Signal(Expr::from_str("sin(2*pi*f*t)").unwrap()),
-        from_str(r#"["f(x) = x", "sin(f(2)*pi*f*t)"]"#).unwrap(),
+        expected,
+        array(r#"["f(x) = x", "sin(f(2)*pi*f*t)"]"#),
         "1 function"
     );
     assert_eq!(
-        Signal(Expr::from_str("sin(2*pi*f*t)").unwrap()),
-        from_str(r#"["a= 2", "f(x) = a", "sin(f(2)*pi*f*t)"]"#).unwrap(),
+        expected,
+        array(r#"["a= 2", "f(x) = a", "sin(f(2)*pi*f*t)"]"#),
         "1 variable, 1 function"
     );
     assert_eq!(
-        Signal(Expr::from_str("sin(2*pi*f*t)").unwrap()),
-        from_str(r#"["e(x)=45*x", "f(x) = 2+e(0)", "sin(f(2)*pi*f*t)"]"#).unwrap(),
+        expected,
+        array(r#"["e(x)=45*x", "f(x) = 2+e(0)", "sin(f(2)*pi*f*t)"]"#),
         "2 functions"
     );
+    assert_eq!(
+        expected,
+        array(r#"["a = -1", "a^2*sin(2*pi*f*t)"]"#),
+        "negative values keep their parentheses"
+    );
 }
@@ -1,4 +1,5 @@
 use meval::Expr;
+use std::collections::HashMap;
 use std::str::FromStr;
 
 use super::*;
@@ -8,20 +9,54 @@ impl Default for Track {
         Self {
             bpm: NonZeroU16::new(120).unwrap(),
             channels: vec![Channel::default()],
+            outputs: Self::stereo(),
+            sample_rate: Self::default_sample_rate(),
+            master: Master::default(),
         }
     }
 }
 
+impl Track {
+    pub(crate) fn stereo() -> NonZeroU16 {
+        NonZeroU16::new(2).unwrap()
+    }
+
+    pub(crate) fn default_sample_rate() -> NonZeroU32 {
+        NonZeroU32::new(SAMPLE_RATE).unwrap()
+    }
+}
+
 impl Default for Channel {
     fn default() -> Self {
         Self {
             signal: Signal::default(),
             notes: Notes::default(),
             tuning: 442.0,
+            params: HashMap::new(),
+            pan: 0.0,
+            gain: 0.0,
+            envelope: Envelope::default(),
+            phase: Phase::default(),
+        }
+    }
+}
+
+impl Default for Master {
+    fn default() -> Self {
+        Self {
+            gain: 0.0,
+            normalise: None,
+            limiter: Self::limited(),
         }
     }
 }
 
+impl Master {
+    pub(crate) fn limited() -> bool {
+        true
+    }
+}
+
 impl Default for Signal {
     fn default() -> Self {
         Self(Expr::from_str("sin(2*pi*f*t)").unwrap())
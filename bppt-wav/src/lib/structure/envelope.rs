@@ -0,0 +1,95 @@
+use serde::Deserialize;
+
+/// Steepness of the exponential and logarithmic curves.
+const CURVATURE: f64 = 5.0;
+
+/// Amplitude envelope of the notes of a channel (ADSR), the default one leaving them untouched.
+#[derive(PartialEq, Debug, Deserialize, Clone, Copy)]
+pub struct Envelope {
+    /// Seconds to rise from silence to full volume
+    #[serde(default)]
+    pub attack: f64,
+    /// Seconds to fall from full volume to the sustain level
+    #[serde(default)]
+    pub decay: f64,
+    /// Level held until the end of the note, from 0 to 1
+    #[serde(default = "Envelope::full")]
+    pub sustain: f64,
+    /// Seconds to fade out once the note is over, ringing past its slot
+    #[serde(default)]
+    pub release: f64,
+    /// Shape of the attack, decay and release slopes
+    #[serde(default)]
+    pub curve: Curve,
+}
+
+/// Shape of an envelope slope.
+#[derive(PartialEq, Eq, Debug, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Curve {
+    /// Constant speed
+    #[default]
+    Linear,
+    /// Slow at first, then faster
+    Exponential,
+    /// Fast at first, then slower
+    Logarithmic,
+}
+
+impl Default for Envelope {
+    fn default() -> Self {
+        Self {
+            attack: 0.0,
+            decay: 0.0,
+            sustain: Self::full(),
+            release: 0.0,
+            curve: Curve::default(),
+        }
+    }
+}
+
+impl Curve {
+    /// Progress along the slope (0 to 1) once a part of its time (0 to 1) went by.
+    fn at(self, x: f64) -> f64 {
+        let x = x.clamp(0.0, 1.0);
+        let exponential = |x: f64| (CURVATURE * x).exp_m1() / CURVATURE.exp_m1();
+        match self {
+            Curve::Linear => x,
+            Curve::Exponential => exponential(x),
+            Curve::Logarithmic => 1.0 - exponential(1.0 - x),
+        }
+    }
+}
+
+impl Envelope {
+    pub(crate) fn full() -> f64 {
+        1.0
+    }
+
+    /// Number of samples of the release.
+    pub(crate) fn release_length(&self, sample_rate: f64) -> usize {
+        (self.release.max(0.0) * sample_rate).round() as usize
+    }
+
+    /// Gain of the i-th sample of a note sounding for `length` samples, followed by its release.
+    pub(crate) fn at(&self, i: usize, length: usize, sample_rate: f64) -> f64 {
+        let held = |i: usize| {
+            let time = i as f64 / sample_rate;
+            if time < self.attack {
+                self.curve.at(time / self.attack)
+            } else if time < self.attack + self.decay {
+                1.0 + (self.sustain - 1.0) * self.curve.at((time - self.attack) / self.decay)
+            } else {
+                self.sustain
+            }
+        };
+        if i < length {
+            return held(i);
+        }
+        let released = (i - length) as f64 / sample_rate;
+        match self.release > 0.0 {
+            true => held(length) * (1.0 - self.curve.at(released / self.release)),
+            false => 0.0,
+        }
+    }
+}
@@ -13,4 +13,6 @@
 //! **[Repository](https://github.com/p6nj/bleeperpreter)**
 
 mod structure;
-pub use structure::{Atom, Notes};
+pub use structure::{
+    Atom, BarError, Direction, Dynamic, FlattenedNoteIterator, Mark, Notes, Span, SyntaxError,
+};
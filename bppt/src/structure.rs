@@ -1,11 +1,21 @@
 use derive_new::new;
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::num::{NonZeroU16, NonZeroU8, NonZeroUsize};
 
+mod bars;
 mod de;
 mod default;
+mod dynamics;
+mod error;
 mod iter;
 
+pub use error::{BarError, SyntaxError};
+pub use iter::FlattenedNoteIterator;
+
+/// Length of the notes before the first length command, as in the backends.
+const INITIAL_LENGTH: u8 = 4;
+
 /// N container. Stores mask atoms from the score and the length of the set used to calculate the notes frequencies.
 /// To iterate through flattened mask atoms (without container atoms), use [`flat_iter`](fn@Notes::flat_iter).
 #[derive(new, PartialEq, Debug, Clone)]
@@ -13,14 +23,28 @@ pub struct Notes {
     /// Length of the note set used to calculate note frequencies.
     pub set: u8,
     pub(crate) score: Vec<Atom>,
+    /// Set indices of the scale used by scale degrees, in ascending order (the whole set if empty).
+    #[new(default)]
+    pub scale: Vec<u8>,
+    /// Time signature (beats and beat unit) that bars must match, if any (see [`check_bars`](fn@Notes::check_bars)).
+    #[new(default)]
+    pub time: Option<(NonZeroU8, NonZeroU8)>,
+    /// Seed of the random choices of the score, so that they are the same on every render.
+    #[new(default)]
+    pub seed: u64,
+    /// Volumes of the dynamic markings, replacing the default ones (see [`Dynamic::default_volume`]).
+    #[new(default)]
+    pub dynamics: HashMap<Dynamic, u8>,
 }
 
 /// Mask atoms are musical bricks from the score that either indicate :
 /// - a sound (or silence)
 /// - a command (to change parametters)
 /// - a wrapper (an element applying rules on the atoms it contains)
+///
 /// To iterate through flattened mask atoms (without container atoms), use [`Notes::flat_iter(&self)`].
 #[derive(PartialEq, Debug, Clone)]
+#[non_exhaustive]
 pub enum Atom {
     /// Set octave
     O(NonZeroU8),
@@ -44,10 +68,112 @@ pub enum Atom {
     VIncr,
     /// Decrease the volume
     VDecr,
-    /// Extend the previous note (implementation may vary)
+    /// Tie : extend the previous note (or rest) by a unit of the current length
     More,
     /// Loop the contained atom sequence n times
     Loop(NonZeroU16, Vec<Atom>),
     /// Tuplet : alter the contained atoms so that the total of their length equals the length of a single note
     Tuplet(Vec<Atom>),
+    /// Slide the volume from its current value to the given one over a span
+    VRamp(u8, Span),
+    /// Set gate : the percentage of each note's length that is actually played, the rest being silent
+    G(NonZeroU8),
+    /// Set the gate of the next note only (staccato, legato...)
+    Articulation(NonZeroU8),
+    /// Set a named parameter, left to the backend to interpret (pan, cutoff, duty cycle...)
+    P(String, f64),
+    /// Play a degree of the scale, starting from 1 (the tonic) and going up in the next octaves past the last degree
+    Degree(NonZeroU8),
+    /// Navigation marker, followed by the flattening iterator at the top level of the score
+    Mark(Mark),
+    /// Bar line at the given byte offset of the score, only used to check bar durations against the time signature
+    Bar(usize),
+    /// Play the atom with the given probability in percent, or rests of the same length instead
+    Chance(u8, Box<Atom>),
+    /// Play one of the atom sequences, picked at random every time
+    Choice(Vec<Vec<Atom>>),
+    /// Play grace notes, taking a fixed share of the length of the following note
+    Grace(Vec<Atom>, Box<Atom>),
+    /// Alternate quickly between a note and its auxiliary for the length of the note
+    Trill(Box<Atom>, Box<Atom>),
+    /// Play a note, its auxiliary and the note again quickly, holding the last one
+    Mordent(Box<Atom>, Box<Atom>),
+    /// Cycle through the notes of a chord in the given number of steps, for the length of a single note
+    Arpeggio(Direction, NonZeroU8, Vec<Atom>),
+    /// Dynamic marking, turned into a volume (or an accent) using the table of the score
+    Dynamic(Dynamic),
+    /// Set the volume of the next note only
+    Accent(u8),
+    /// Play the note the given number of set steps above (or below) the previous one, changing octaves when needed
+    Interval(i16),
+    /// Byte offset of the next atom in the score, put there by the parser so that flattened atoms can be traced back to the score (see [`FlattenedNoteIterator::offset`])
+    At(usize),
+}
+
+/// Time covered by a ramp.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Span {
+    /// The next n notes or rests
+    Notes(NonZeroU16),
+    /// The duration of a single note of the given length
+    Length(NonZeroU8),
+}
+
+/// Navigation markers of repeat structures (da capo, dal segno...).
+///
+/// Jumps (D.C. and D.S.) are taken once. After a jump, loops are played once, a fine ends the score and a "to coda" skips to the coda.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Mark {
+    /// Destination of a dal segno
+    Segno,
+    /// Jump to the coda after a repeat
+    ToCoda,
+    /// Destination of a "to coda"
+    Coda,
+    /// End of the score after a repeat
+    Fine,
+    /// Da capo : jump back to the beginning of the score
+    DaCapo,
+    /// Dal segno : jump back to the segno (or the beginning of the score if there is none)
+    DalSegno,
+}
+
+/// Order in which an arpeggio plays the notes of its chord.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Direction {
+    /// From the lowest note to the highest
+    Up,
+    /// From the highest note to the lowest
+    Down,
+    /// Up, then back down without repeating the highest and lowest notes
+    UpDown,
+}
+
+/// Dynamic markings from sheet music.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum Dynamic {
+    /// Pianississimo
+    Ppp,
+    /// Pianissimo
+    Pp,
+    /// Piano
+    P,
+    /// Mezzo-piano
+    Mp,
+    /// Mezzo-forte
+    Mf,
+    /// Forte
+    F,
+    /// Fortissimo
+    Ff,
+    /// Fortississimo
+    Fff,
+    /// Sforzando (accent)
+    Sf,
+    /// Sforzato (accent)
+    Sfz,
+    /// Sforzatissimo (accent)
+    Sffz,
+    /// Forzando (accent)
+    Fz,
 }
@@ -0,0 +1,162 @@
+use super::*;
+
+#[cfg(test)]
+mod tests;
+
+impl Notes {
+    /// Check that every bar lasts as long as the time signature says, if there is one.
+    ///
+    /// Durations account for length changes, tuplets and ties (`+`, one unit of the current length).
+    /// The first bar may be shorter (pickup), and so may the last one when the score doesn't end with a bar line.
+    /// The options of every random choice must last as long as each other and change the length the same way, so that the bars don't depend on the seed.
+    pub fn check_bars(&self) -> Result<(), BarError> {
+        let Some((beats, unit)) = self.time else {
+            return Ok(());
+        };
+        if let Some(options) = choices(&self.score)
+            .into_iter()
+            .find(|options| !self.even(options))
+        {
+            return Err(BarError::UnevenChoice(options.to_vec()));
+        }
+        let expected = reduce(u64::from(beats.get()), u64::from(unit.get()));
+        let mut meter = Meter::new(u64::from(INITIAL_LENGTH));
+        let mut bar = 1;
+        for atom in self.flat_iter() {
+            if let (Some(duration), Atom::Bar(offset)) = (meter.count(&atom), &atom) {
+                let pickup = bar == 1 && shorter(duration, expected);
+                if duration != expected && !pickup {
+                    return Err(BarError::Length {
+                        bar,
+                        offset: Some(*offset),
+                        duration,
+                        expected: (beats, unit),
+                    });
+                }
+                bar += 1;
+            }
+        }
+        match shorter(expected, meter.duration) {
+            true => Err(BarError::Length {
+                bar,
+                offset: None,
+                duration: meter.duration,
+                expected: (beats, unit),
+            }),
+            false => Ok(()),
+        }
+    }
+
+    /// Whether the options of a random choice have the same bars, duration and length changes from any length.
+    ///
+    /// Choices nested in the options are even once checked themselves, so whatever the seed picks in them doesn't matter.
+    fn even(&self, options: &[Vec<Atom>]) -> bool {
+        let effects = options
+            .iter()
+            .map(|option| {
+                let atoms = self.flat_inner(option.clone()).collect::<Vec<Atom>>();
+                // ties before the first note extend the note before the choice, whatever its tuplet
+                let ties = atoms
+                    .iter()
+                    .take_while(|atom| !matches!(atom, Atom::N(..) | Atom::Rest(_)))
+                    .filter(|atom| **atom == Atom::More)
+                    .count();
+                let counts = (1..=u64::from(u8::MAX))
+                    .map(|length| {
+                        let mut meter = Meter::new(length);
+                        let bars = atoms
+                            .iter()
+                            .filter_map(|atom| meter.count(atom))
+                            .collect::<Vec<_>>();
+                        (bars, meter)
+                    })
+                    .collect::<Vec<_>>();
+                (ties, counts)
+            })
+            .collect::<Vec<_>>();
+        effects.windows(2).all(|pair| pair[0] == pair[1])
+    }
+}
+
+/// Running count of the duration of the current bar through flattened atoms.
+#[derive(PartialEq, Debug)]
+struct Meter {
+    length: u64,
+    tup: u64,
+    /// Duration of the current bar in whole notes, as a reduced fraction
+    duration: (u64, u64),
+}
+
+impl Meter {
+    fn new(length: u64) -> Self {
+        Self {
+            length,
+            tup: 1,
+            duration: (0, 1),
+        }
+    }
+
+    /// Count an atom, returning the duration of the bar it ends if it is a bar line.
+    fn count(&mut self, atom: &Atom) -> Option<(u64, u64)> {
+        match atom {
+            Atom::N(_, t) | Atom::Rest(t) => {
+                self.tup = t.get() as u64;
+                self.duration = add(self.duration, (1, self.length * self.tup));
+            }
+            Atom::More => self.duration = add(self.duration, (1, self.length * self.tup)),
+            Atom::L(l) => self.length = u64::from(l.get()),
+            Atom::LIncr => self.length = self.length.saturating_mul(2).min(u64::from(u8::MAX)),
+            Atom::LDecr => self.length = (self.length / 2).max(1),
+            Atom::Bar(_) => return Some(std::mem::replace(&mut self.duration, (0, 1))),
+            _ => {}
+        }
+        None
+    }
+}
+
+/// Options of the random choices anywhere in the atoms, including inside containers and other choices.
+fn choices(atoms: &[Atom]) -> Vec<&[Vec<Atom>]> {
+    atoms
+        .iter()
+        .flat_map(|atom| match atom {
+            Atom::Choice(options) => std::iter::once(options.as_slice())
+                .chain(options.iter().flat_map(|option| choices(option)))
+                .collect(),
+            Atom::Loop(_, v) | Atom::Tuplet(v) | Atom::Arpeggio(_, _, v) => choices(v),
+            Atom::Chance(_, atom) => choices(std::slice::from_ref(atom)),
+            Atom::Grace(graces, main) => {
+                let mut found = choices(graces);
+                found.extend(choices(std::slice::from_ref(main)));
+                found
+            }
+            Atom::Trill(main, auxiliary) | Atom::Mordent(main, auxiliary) => {
+                let mut found = choices(std::slice::from_ref(main));
+                found.extend(choices(std::slice::from_ref(auxiliary)));
+                found
+            }
+            _ => vec![],
+        })
+        .collect()
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    match b {
+        0 => a,
+        b => gcd(b, a % b),
+    }
+}
+
+fn reduce(num: u64, den: u64) -> (u64, u64) {
+    let gcd = gcd(num, den).max(1);
+    (num / gcd, den / gcd)
+}
+
+fn add((a, b): (u64, u64), (c, d): (u64, u64)) -> (u64, u64) {
+    let den = b / gcd(b, d) * d;
+    reduce(a * (den / b) + c * (den / d), den)
+}
+
+/// Whether a fraction is strictly smaller than another.
+fn shorter((a, b): (u64, u64), (c, d): (u64, u64)) -> bool {
+    u128::from(a) * u128::from(d) < u128::from(c) * u128::from(b)
+}
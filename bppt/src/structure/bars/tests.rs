@@ -0,0 +1,113 @@
+use std::num::NonZeroU8;
+
+use crate::{BarError, Notes};
+
+fn check(time: (u8, u8), score: &str) -> Result<(), BarError> {
+    let mut notes = Notes::parse("abcdefg", score).unwrap();
+    notes.time = Some((
+        NonZeroU8::new(time.0).unwrap(),
+        NonZeroU8::new(time.1).unwrap(),
+    ));
+    notes.check_bars()
+}
+
+#[test]
+fn durations() {
+    assert_eq!(Ok(()), check((4, 4), "abcd|$8 abcdefga|"));
+    assert_eq!(
+        Ok(()),
+        check((3, 4), "a'b|`a[bcd]c|$2. `+|"),
+        "tuplets and ties"
+    );
+    assert_eq!(Ok(()), check((6, 8), "(2$8 abcdef|)"), "loops");
+    assert_eq!(Ok(()), check((4, 4), "a|abcd|ab"), "pickup and last bar");
+}
+
+/// Number of the bar a bar error is about, if it is about a bar.
+fn bar(err: BarError) -> Option<usize> {
+    match err {
+        BarError::Length { bar, .. } => Some(bar),
+        BarError::UnevenChoice(_) => None,
+    }
+}
+
+#[test]
+fn mismatches() {
+    assert_eq!(
+        Err(BarError::Length {
+            bar: 2,
+            offset: Some(14),
+            duration: (15, 16),
+            expected: (NonZeroU8::new(4).unwrap(), NonZeroU8::new(4).unwrap()),
+        }),
+        check((4, 4), "abcd|abc$16abc|"),
+        "one sixteenth short"
+    );
+    assert_eq!(
+        Some(1),
+        check((3, 4), "abcd|abc").err().and_then(bar),
+        "long pickup"
+    );
+    assert_eq!(
+        Some(2),
+        check((3, 4), "abc|abcd").err().and_then(bar),
+        "long last bar"
+    );
+    assert_eq!(
+        Some(vec![Some(9), Some(5), None]),
+        [
+            check((2, 4), "(2ab|)abc|"),
+            check((2, 4), "(2abc|)"),
+            check((2, 4), "ab|abc"),
+        ]
+        .into_iter()
+        .map(|result| match result {
+            Err(BarError::Length { offset, .. }) => Some(offset),
+            _ => None,
+        })
+        .collect(),
+        "offsets of the bar lines in the score"
+    );
+}
+
+#[test]
+fn choices() {
+    let uneven = |score| {
+        (0..6).all(|seed| {
+            let mut notes = Notes::parse("abcdefg", score).unwrap();
+            notes.time = Some((NonZeroU8::new(4).unwrap(), NonZeroU8::new(4).unwrap()));
+            notes.seed = seed;
+            matches!(notes.check_bars(), Err(BarError::UnevenChoice(_)))
+        })
+    };
+    assert!(uneven("?(a;aa)|"), "whatever the seed");
+    assert!(uneven("?($8aa;a)b|"), "different lengths after the choice");
+    assert!(uneven("(2?(a;b|))ab"), "bar lines in one option only");
+    assert!(uneven("a[?(ab;a)]|"), "inside a container");
+    assert_eq!(Ok(()), check((4, 4), "?(ab;ba)?(a;.)c|"));
+    assert_eq!(
+        Ok(()),
+        check((4, 4), "?($8aa$4;$4a)bcd|"),
+        "same length after"
+    );
+    assert_eq!(Ok(()), check((4, 4), "(2?(a;?(b;c)))ab|"), "nested choices");
+}
+
+#[test]
+fn deserialization() {
+    let notes: Notes =
+        serde_json::from_str(r#"{"set": "abc", "score": "abc|abc|", "time": "3/4"}"#).unwrap();
+    assert_eq!(
+        Some((NonZeroU8::new(3).unwrap(), NonZeroU8::new(4).unwrap())),
+        notes.time
+    );
+    assert!(
+        serde_json::from_str::<Notes>(r#"{"set": "abc", "score": "ab|", "time": "3/4"}"#).is_ok(),
+        "pickup"
+    );
+    assert!(
+        serde_json::from_str::<Notes>(r#"{"set": "abc", "score": "abc|ab|", "time": "3/4"}"#)
+            .is_err()
+    );
+    assert!(serde_json::from_str::<Notes>(r#"{"set": "abc", "score": "a", "time": "3"}"#).is_err());
+}
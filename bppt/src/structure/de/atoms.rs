@@ -1,15 +1,15 @@
 use nom::branch::alt;
-use nom::bytes::complete::take_till1;
-use nom::character::complete::{char, multispace0, one_of};
+use nom::bytes::complete::tag;
+use nom::character::complete::{alpha1, alphanumeric1, char, digit1, multispace0, one_of};
 use nom::character::complete::{u16, u8};
-use nom::combinator::{consumed, map_opt, map_res, opt, value, verify};
+use nom::combinator::{all_consuming, cut, map, map_opt, map_res, opt, recognize, value, verify};
 use nom::error::{Error, ErrorKind};
-use nom::multi::many0;
-use nom::sequence::{pair, preceded};
-use nom::{Err, IResult};
+use nom::multi::{many0, many0_count};
+use nom::sequence::{pair, preceded, separated_pair, terminated, tuple};
+use nom::{Err, IResult, Offset};
 use std::num::{NonZeroU16, NonZeroU8, NonZeroUsize};
 
-use crate::structure::Atom;
+use crate::structure::{Atom, Direction, Dynamic, Mark, Span, SyntaxError};
 
 #[cfg(test)]
 mod tests;
@@ -29,11 +29,43 @@ const LOOP_OUT: char = ')';
 const TUP_IN: char = '[';
 const TUP_OUT: char = ']';
 const MORE: char = '+';
+const RAMP: char = '~';
+const RAMP_NOTES: char = ':';
+const GATE: char = '%';
+const STACCATO: char = '*';
+const LEGATO: char = '=';
+
+const PARAM: char = '#';
+const PARAM_VALUE: char = '=';
+const DEGREE: char = '&';
+const INTERVAL_UP: char = '+';
+const INTERVAL_DOWN: char = '-';
+const MARK: char = '/';
+const BAR: char = '|';
+const EUCLID_IN: char = '{';
+const EUCLID_OUT: char = '}';
+const EUCLID_SEP: char = ',';
+const EUCLID_PHRASE: char = ':';
+const RANDOM: char = '?';
+const CHOICE_SEP: char = ';';
+const GRACE: char = ',';
+const TRILL: char = '~';
+const MORDENT: char = '-';
+
+const SEGNO: &str = "segno";
+const TO_CODA: &str = "tocoda";
+const CODA: &str = "coda";
+const FINE: &str = "fine";
+const DA_CAPO: &str = "dc";
+const DAL_SEGNO: &str = "ds";
+
+const STACCATO_GATE: u8 = 50;
+const LEGATO_GATE: u8 = 100;
 
 type R<'a> = Result<Atom, Err<Error<&'a str>>>;
 type LeResult<'a> = IResult<&'a str, Atom>;
 
-fn octave(i: &str) -> LeResult {
+fn octave(i: &str) -> LeResult<'_> {
     map_res(
         map_opt(
             verify(preceded(char(OCTAVE), u8), |n| NonZeroU8::new(*n).is_some()),
@@ -43,7 +75,7 @@ fn octave(i: &str) -> LeResult {
     )(i)
 }
 
-fn length(i: &str) -> LeResult {
+fn length(i: &str) -> LeResult<'_> {
     map_res(
         map_opt(
             verify(preceded(char(LENGTH), u8), |n| NonZeroU8::new(*n).is_some()),
@@ -53,8 +85,151 @@ fn length(i: &str) -> LeResult {
     )(i)
 }
 
-fn volume(i: &str) -> LeResult {
-    map_res(preceded(char(VOLUME), u8), move |n| R::Ok(Atom::V(n)))(i)
+fn volume(i: &str) -> LeResult<'_> {
+    preceded(
+        char(VOLUME),
+        alt((map(u8, Atom::V), map(dynamic, Atom::Dynamic))),
+    )(i)
+}
+
+/// The longest dynamic marking at the start of the input, so that a note continuing a marking (`f` after `!f`) needs a space before it.
+fn dynamic(i: &str) -> IResult<&str, Dynamic> {
+    Dynamic::all()
+        .find_map(|dynamic| {
+            tag::<_, _, Error<&str>>(dynamic.name())(i)
+                .ok()
+                .map(|(rest, _)| (rest, dynamic))
+        })
+        .ok_or_else(|| Err::Error(Error::new(i, ErrorKind::Tag)))
+}
+
+fn span(i: &str) -> IResult<&str, Span> {
+    alt((
+        map(
+            preceded(
+                char(RAMP_NOTES),
+                map_opt(
+                    verify(u16, |n| NonZeroU16::new(*n).is_some()),
+                    NonZeroU16::new,
+                ),
+            ),
+            Span::Notes,
+        ),
+        map(
+            preceded(
+                char(LENGTH),
+                map_opt(verify(u8, |n| NonZeroU8::new(*n).is_some()), NonZeroU8::new),
+            ),
+            Span::Length,
+        ),
+    ))(i)
+}
+
+fn ramp(i: &str) -> LeResult<'_> {
+    map_res(
+        preceded(pair(char(RAMP), char(VOLUME)), pair(u8, span)),
+        |(v, span)| R::Ok(Atom::VRamp(v, span)),
+    )(i)
+}
+
+fn gate(i: &str) -> LeResult<'_> {
+    map_res(
+        map_opt(
+            verify(preceded(char(GATE), u8), |n| (1..=100).contains(n)),
+            NonZeroU8::new,
+        ),
+        |n| R::Ok(Atom::G(n)),
+    )(i)
+}
+
+fn staccato(i: &str) -> LeResult<'_> {
+    value(
+        Atom::Articulation(NonZeroU8::new(STACCATO_GATE).unwrap()),
+        char(STACCATO),
+    )(i)
+}
+
+fn legato(i: &str) -> LeResult<'_> {
+    value(
+        Atom::Articulation(NonZeroU8::new(LEGATO_GATE).unwrap()),
+        char(LEGATO),
+    )(i)
+}
+
+fn articulation(i: &str) -> LeResult<'_> {
+    alt((gate, staccato, legato))(i)
+}
+
+fn parameter(i: &str) -> LeResult<'_> {
+    map_res(
+        preceded(
+            char(PARAM),
+            separated_pair(
+                recognize(pair(
+                    alt((alpha1, recognize(char('_')))),
+                    many0_count(alt((alphanumeric1, recognize(char('_'))))),
+                )),
+                char(PARAM_VALUE),
+                decimal,
+            ),
+        ),
+        |(name, value): (&str, f64)| R::Ok(Atom::P(name.to_string(), value)),
+    )(i)
+}
+
+/// A plain decimal number (`-?digits(.digits)?`), so that a rest can follow it directly.
+fn decimal(i: &str) -> IResult<&str, f64> {
+    map_res(
+        recognize(tuple((
+            opt(char('-')),
+            digit1,
+            opt(pair(char('.'), digit1)),
+        ))),
+        str::parse,
+    )(i)
+}
+
+fn degree(i: &str) -> LeResult<'_> {
+    map_res(
+        map_opt(
+            verify(preceded(char(DEGREE), u8), |n| NonZeroU8::new(*n).is_some()),
+            NonZeroU8::new,
+        ),
+        |n| R::Ok(Atom::Degree(n)),
+    )(i)
+}
+
+fn interval(i: &str) -> LeResult<'_> {
+    map_res(
+        preceded(
+            char(DEGREE),
+            pair(
+                alt((
+                    value(1i16, char(INTERVAL_UP)),
+                    value(-1i16, char(INTERVAL_DOWN)),
+                )),
+                u8,
+            ),
+        ),
+        |(sign, steps)| R::Ok(Atom::Interval(sign * i16::from(steps))),
+    )(i)
+}
+
+fn mark(i: &str) -> LeResult<'_> {
+    map_res(
+        preceded(
+            char(MARK),
+            alt((
+                value(Mark::Segno, tag(SEGNO)),
+                value(Mark::ToCoda, tag(TO_CODA)),
+                value(Mark::Coda, tag(CODA)),
+                value(Mark::Fine, tag(FINE)),
+                value(Mark::DaCapo, tag(DA_CAPO)),
+                value(Mark::DalSegno, tag(DAL_SEGNO)),
+            )),
+        ),
+        |mark| R::Ok(Atom::Mark(mark)),
+    )(i)
 }
 
 fn note<'a>(notes: &'a str) -> impl FnMut(&'a str) -> IResult<&'a str, Atom> {
@@ -66,43 +241,54 @@ fn note<'a>(notes: &'a str) -> impl FnMut(&'a str) -> IResult<&'a str, Atom> {
     })
 }
 
-fn rest(i: &str) -> LeResult {
+fn rest(i: &str) -> LeResult<'_> {
     value(Atom::Rest(NonZeroUsize::new(1).unwrap()), char(REST))(i)
 }
 
-fn octaveincr(i: &str) -> LeResult {
+fn octaveincr(i: &str) -> LeResult<'_> {
     value(Atom::OIncr, char(OCTAVEINCR))(i)
 }
 
-fn octavedecr(i: &str) -> LeResult {
+fn octavedecr(i: &str) -> LeResult<'_> {
     value(Atom::ODecr, char(OCTAVEDECR))(i)
 }
 
-fn lengthincr(i: &str) -> LeResult {
+fn lengthincr(i: &str) -> LeResult<'_> {
     value(Atom::LIncr, char(LENGTHINCR))(i)
 }
 
-fn lengthdecr(i: &str) -> LeResult {
+fn lengthdecr(i: &str) -> LeResult<'_> {
     value(Atom::LDecr, char(LENGTHDECR))(i)
 }
 
-fn volumeincr(i: &str) -> LeResult {
+fn volumeincr(i: &str) -> LeResult<'_> {
     value(Atom::VIncr, char(VOLUMEINCR))(i)
 }
 
-fn volumedecr(i: &str) -> LeResult {
+fn volumedecr(i: &str) -> LeResult<'_> {
     value(Atom::VDecr, char(VOLUMEDECR))(i)
 }
 
-fn more(i: &str) -> LeResult {
+fn more(i: &str) -> LeResult<'_> {
     value(Atom::More, char(MORE))(i)
 }
 
+/// A bar line, at its byte offset in the whole `score` (containers parse slices of it).
+fn bar<'a>(score: &'a str) -> impl FnMut(&'a str) -> LeResult<'a> {
+    move |i| value(Atom::Bar(score.offset(i)), char(BAR))(i)
+}
+
+fn modifier(i: &str) -> LeResult<'_> {
+    alt((
+        octaveincr, octavedecr, lengthincr, lengthdecr, volumeincr, volumedecr,
+    ))(i)
+}
+
 fn junk(i: &str) -> IResult<&str, ()> {
     value((), multispace0)(i)
 }
 
-fn atom<'a>(noteset: &'a str) -> impl FnMut(&'a str) -> IResult<&'a str, Atom> {
+fn atom<'a>(noteset: &'a str, score: &'a str) -> impl FnMut(&'a str) -> IResult<&'a str, Atom> {
     preceded(
         junk,
         alt((
@@ -111,47 +297,80 @@ fn atom<'a>(noteset: &'a str) -> impl FnMut(&'a str) -> IResult<&'a str, Atom> {
             length,
             volume,
             rest,
-            octaveincr,
-            octavedecr,
-            lengthincr,
-            lengthdecr,
-            volumeincr,
-            volumedecr,
+            modifier,
             more,
-            r#loop(noteset),
-            tuplet(noteset),
+            ramp,
+            articulation,
+            parameter,
+            degree,
+            interval,
+            mark,
+            bar(score),
+            r#loop(noteset, score),
+            tuplet(noteset, score),
+            generator(noteset, score),
+            random(noteset, score),
+            ornament(noteset, score),
         )),
     )
 }
 
+/// An atom preceded by its byte offset in the whole `score`.
+fn located<'a>(
+    noteset: &'a str,
+    score: &'a str,
+) -> impl FnMut(&'a str) -> IResult<&'a str, [Atom; 2]> {
+    move |i| {
+        let (i, ()) = junk(i)?;
+        let (rest, atom) = atom(noteset, score)(i)?;
+        Ok((rest, [Atom::At(score.offset(i)), atom]))
+    }
+}
+
+fn sequence<'a>(
+    noteset: &'a str,
+    score: &'a str,
+) -> impl FnMut(&'a str) -> IResult<&'a str, Vec<Atom>> {
+    terminated(
+        map(many0(located(noteset, score)), |atoms| {
+            atoms.into_iter().flatten().collect()
+        }),
+        junk,
+    )
+}
+
 fn close(in_tag: char, out_tag: char) -> impl FnMut(&str) -> IResult<&str, ()> {
     move |i| {
         let mut lvl = 1u8;
-        let mut input = i;
-        while let Ok((r, _)) =
-            take_till1::<_, &str, Error<&str>>(|c| c == in_tag || c == out_tag)(input)
-        {
-            {
-                let ch = r
-                    .chars()
-                    .next()
-                    .ok_or(Err::Error(Error::new("", ErrorKind::Complete)))?;
-                if ch == in_tag {
-                    lvl += 1
-                } else {
-                    lvl -= 1;
-                    if lvl == 0 {
-                        return Ok((&r[1..], ()));
-                    }
+        for (index, c) in i.char_indices() {
+            if c == in_tag {
+                lvl += 1;
+            } else if c == out_tag {
+                lvl -= 1;
+                if lvl == 0 {
+                    return Ok((&i[index + c.len_utf8()..], ()));
                 }
             }
-            input = r;
         }
-        Err(Err::Error(Error::new("", ErrorKind::Complete)))
+        Err(Err::Error(Error::new(i, ErrorKind::Complete)))
+    }
+}
+
+/// Parse the content of a container up to its closing tag, failing for good at the first bad atom so the error keeps its position.
+fn inside<'a>(
+    noteset: &'a str,
+    score: &'a str,
+    in_tag: char,
+    out_tag: char,
+) -> impl FnMut(&'a str) -> IResult<&'a str, Vec<Atom>> {
+    move |i| {
+        let (rest, inner) = recognize(close(in_tag, out_tag))(i)?;
+        let (_, atoms) = cut(all_consuming(sequence(noteset, score)))(&inner[..inner.len() - 1])?;
+        Ok((rest, atoms))
     }
 }
 
-fn r#loop<'a>(noteset: &'a str) -> impl FnMut(&'a str) -> LeResult + 'a {
+fn r#loop<'a>(noteset: &'a str, score: &'a str) -> impl FnMut(&'a str) -> LeResult<'a> + 'a {
     map_res(
         preceded(
             char(LOOP_IN),
@@ -160,34 +379,228 @@ fn r#loop<'a>(noteset: &'a str) -> impl FnMut(&'a str) -> LeResult + 'a {
                     verify(u16, |res| NonZeroU16::new(*res).is_some()),
                     NonZeroU16::new,
                 )),
-                consumed(close(LOOP_IN, LOOP_OUT)),
+                inside(noteset, score, LOOP_IN, LOOP_OUT),
             ),
         ),
-        move |(repeat, (inner, _))| {
+        move |(repeat, inner)| {
             R::Ok(Atom::Loop(
                 repeat.unwrap_or(NonZeroU16::new(2).unwrap()),
-                many0(atom(noteset))(inner)?.1,
+                inner,
             ))
         },
     )
 }
 
-fn tuplet<'a>(noteset: &'a str) -> impl FnMut(&'a str) -> LeResult + 'a {
+fn tuplet<'a>(noteset: &'a str, score: &'a str) -> impl FnMut(&'a str) -> LeResult<'a> + 'a {
+    map_res(
+        preceded(
+            char(TUP_IN),
+            verify(
+                inside(noteset, score, TUP_IN, TUP_OUT),
+                |res: &Vec<Atom>| !res.is_empty(),
+            ),
+        ),
+        |inner| R::Ok(Atom::Tuplet(inner)),
+    )
+}
+
+/// Spread `hits` onsets as evenly as possible over `steps` (Bjorklund's algorithm).
+fn euclidean(hits: usize, steps: usize) -> Vec<bool> {
+    let mut front = vec![vec![true]; hits];
+    let mut back = vec![vec![false]; steps - hits];
+    while back.len() > 1 && !front.is_empty() {
+        let pairs = front.len().min(back.len());
+        let rest = match front.len() > pairs {
+            true => front.split_off(pairs),
+            false => back.split_off(pairs),
+        };
+        front = front
+            .into_iter()
+            .zip(back)
+            .map(|(mut group, tail)| {
+                group.extend(tail);
+                group
+            })
+            .collect();
+        back = rest;
+    }
+    front.into_iter().chain(back).flatten().collect()
+}
+
+fn euclid<'a>(noteset: &'a str, score: &'a str) -> impl FnMut(&'a str) -> LeResult<'a> + 'a {
     map_res(
-        preceded(char(TUP_IN), consumed(close(TUP_IN, TUP_OUT))),
-        move |(inner, _)| {
-            R::Ok(Atom::Tuplet(
-                verify(many0(atom(noteset)), |res: &Vec<Atom>| !res.is_empty())(inner)?.1,
+        preceded(
+            char(EUCLID_IN),
+            pair(
+                verify(
+                    pair(
+                        separated_pair(u16, char(EUCLID_SEP), u16),
+                        opt(preceded(char(EUCLID_SEP), u16)),
+                    ),
+                    |((hits, steps), _)| *steps > 0 && hits <= steps,
+                ),
+                preceded(
+                    char(EUCLID_PHRASE),
+                    verify(
+                        inside(noteset, score, EUCLID_IN, EUCLID_OUT),
+                        |res: &Vec<Atom>| !res.is_empty(),
+                    ),
+                ),
+            ),
+        ),
+        |(((hits, steps), rotation), phrase)| {
+            let mut pattern = euclidean(usize::from(hits), usize::from(steps));
+            pattern.rotate_left(usize::from(rotation.unwrap_or(0) % steps));
+            R::Ok(Atom::Loop(
+                NonZeroU16::new(1).unwrap(),
+                pattern
+                    .into_iter()
+                    .map(|hit| match hit {
+                        true => Atom::Tuplet(phrase.clone()),
+                        false => Atom::Rest(NonZeroUsize::new(1).unwrap()),
+                    })
+                    .collect(),
             ))
         },
     )
 }
 
+fn chance<'a>(noteset: &'a str, score: &'a str) -> impl FnMut(&'a str) -> LeResult<'a> + 'a {
+    map_res(
+        preceded(
+            char(RANDOM),
+            pair(verify(u8, |p| *p <= 100), move |i| atom(noteset, score)(i)),
+        ),
+        |(p, atom)| R::Ok(Atom::Chance(p, Box::new(atom))),
+    )
+}
+
+fn choice<'a>(noteset: &'a str, score: &'a str) -> impl FnMut(&'a str) -> LeResult<'a> + 'a {
+    move |i| {
+        let (rest, inner) = preceded(
+            pair(char(RANDOM), char(LOOP_IN)),
+            recognize(close(LOOP_IN, LOOP_OUT)),
+        )(i)?;
+        let inner = &inner[..inner.len() - 1];
+        let mut depth = 0usize;
+        let options = inner
+            .split(|c| {
+                match c {
+                    LOOP_IN | TUP_IN | EUCLID_IN => depth += 1,
+                    LOOP_OUT | TUP_OUT | EUCLID_OUT => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+                c == CHOICE_SEP && depth == 0
+            })
+            .map(|option| {
+                cut(all_consuming(sequence(noteset, score)))(option).map(|(_, atoms)| atoms)
+            })
+            .collect::<Result<Vec<Vec<Atom>>, _>>()?;
+        Ok((rest, Atom::Choice(options)))
+    }
+}
+
+fn pitch<'a>(noteset: &'a str) -> impl FnMut(&'a str) -> LeResult<'a> {
+    alt((note(noteset), degree))
+}
+
+fn grace<'a>(noteset: &'a str, score: &'a str) -> impl FnMut(&'a str) -> LeResult<'a> + 'a {
+    map_res(
+        preceded(
+            char(GRACE),
+            pair(
+                alt((
+                    preceded(
+                        char(LOOP_IN),
+                        verify(
+                            inside(noteset, score, LOOP_IN, LOOP_OUT),
+                            |res: &Vec<Atom>| !res.is_empty(),
+                        ),
+                    ),
+                    map(pitch(noteset), |atom| vec![atom]),
+                )),
+                preceded(junk, pitch(noteset)),
+            ),
+        ),
+        |(graces, main)| R::Ok(Atom::Grace(graces, Box::new(main))),
+    )
+}
+
+fn trill<'a>(noteset: &'a str) -> impl FnMut(&'a str) -> LeResult<'a> {
+    map_res(
+        preceded(char(TRILL), pair(pitch(noteset), pitch(noteset))),
+        |(main, auxiliary)| R::Ok(Atom::Trill(Box::new(main), Box::new(auxiliary))),
+    )
+}
+
+fn mordent<'a>(noteset: &'a str) -> impl FnMut(&'a str) -> LeResult<'a> {
+    map_res(
+        preceded(char(MORDENT), pair(pitch(noteset), pitch(noteset))),
+        |(main, auxiliary)| R::Ok(Atom::Mordent(Box::new(main), Box::new(auxiliary))),
+    )
+}
+
+fn ornament<'a>(noteset: &'a str, score: &'a str) -> impl FnMut(&'a str) -> LeResult<'a> + 'a {
+    alt((grace(noteset, score), trill(noteset), mordent(noteset)))
+}
+
+fn random<'a>(noteset: &'a str, score: &'a str) -> impl FnMut(&'a str) -> LeResult<'a> + 'a {
+    alt((choice(noteset, score), chance(noteset, score)))
+}
+
+fn arpeggio<'a>(noteset: &'a str) -> impl FnMut(&'a str) -> LeResult<'a> + 'a {
+    map_res(
+        preceded(
+            char(EUCLID_IN),
+            pair(
+                pair(
+                    alt((
+                        value(Direction::UpDown, pair(char(OCTAVEINCR), char(OCTAVEDECR))),
+                        value(Direction::Up, char(OCTAVEINCR)),
+                        value(Direction::Down, char(OCTAVEDECR)),
+                    )),
+                    map_opt(verify(u8, |n| NonZeroU8::new(*n).is_some()), NonZeroU8::new),
+                ),
+                preceded(
+                    char(EUCLID_PHRASE),
+                    verify(
+                        terminated(
+                            many0(preceded(
+                                junk,
+                                alt((pitch(noteset), octaveincr, octavedecr)),
+                            )),
+                            pair(junk, char(EUCLID_OUT)),
+                        ),
+                        |res: &Vec<Atom>| {
+                            res.iter()
+                                .any(|atom| matches!(atom, Atom::N(..) | Atom::Degree(_)))
+                        },
+                    ),
+                ),
+            ),
+        ),
+        |((direction, steps), chord)| R::Ok(Atom::Arpeggio(direction, steps, chord)),
+    )
+}
+
+fn generator<'a>(noteset: &'a str, score: &'a str) -> impl FnMut(&'a str) -> LeResult<'a> + 'a {
+    alt((euclid(noteset, score), arpeggio(noteset)))
+}
+
 impl Atom {
-    pub(crate) fn parse<'a>(
-        input: &'a str,
-        noteset: &'a str,
-    ) -> Result<Vec<Atom>, Err<Error<&'a str>>> {
-        Ok(many0(atom(noteset))(input)?.1)
+    pub(crate) fn parse(input: &str, noteset: &str) -> Result<Vec<Atom>, SyntaxError> {
+        all_consuming(sequence(noteset, input))(input)
+            .map(|(_, atoms)| atoms)
+            .map_err(|err| {
+                let rest = match err {
+                    Err::Error(e) | Err::Failure(e) => e.input,
+                    Err::Incomplete(_) => &input[input.len()..],
+                };
+                let offset = input.offset(rest);
+                SyntaxError {
+                    offset,
+                    found: input[offset..].chars().next(),
+                }
+            })
     }
 }
@@ -86,10 +86,17 @@ fn loop_() {
             "",
             Atom::Loop(
                 NonZeroU16::new(2).unwrap(),
-                vec![Atom::N(2, NonZeroUsize::new(1).unwrap()); 3]
+                vec![
+                    Atom::At(1),
+                    Atom::N(2, NonZeroUsize::new(1).unwrap()),
+                    Atom::At(2),
+                    Atom::N(2, NonZeroUsize::new(1).unwrap()),
+                    Atom::At(3),
+                    Atom::N(2, NonZeroUsize::new(1).unwrap())
+                ]
             )
         )),
-        super::r#loop("abcde")(&input)
+        super::r#loop("abcde", &input)(&input)
     );
     let input = format!("{LOOP_IN}45ccc{LOOP_OUT}");
     assert_eq!(
@@ -97,10 +104,17 @@ fn loop_() {
             "",
             Atom::Loop(
                 NonZeroU16::new(45).unwrap(),
-                vec![Atom::N(2, NonZeroUsize::new(1).unwrap()); 3]
+                vec![
+                    Atom::At(3),
+                    Atom::N(2, NonZeroUsize::new(1).unwrap()),
+                    Atom::At(4),
+                    Atom::N(2, NonZeroUsize::new(1).unwrap()),
+                    Atom::At(5),
+                    Atom::N(2, NonZeroUsize::new(1).unwrap())
+                ]
             )
         )),
-        super::r#loop("abcde")(&input)
+        super::r#loop("abcde", &input)(&input)
     );
 }
 #[test]
@@ -109,8 +123,397 @@ fn tuplet() {
     assert_eq!(
         Ok((
             "",
-            Atom::Tuplet(vec![Atom::N(2, NonZeroUsize::new(1).unwrap()); 3])
+            Atom::Tuplet(vec![
+                Atom::At(1),
+                Atom::N(2, NonZeroUsize::new(1).unwrap()),
+                Atom::At(2),
+                Atom::N(2, NonZeroUsize::new(1).unwrap()),
+                Atom::At(3),
+                Atom::N(2, NonZeroUsize::new(1).unwrap())
+            ])
         )),
-        super::tuplet("abcde")(&input)
+        super::tuplet("abcde", &input)(&input)
     );
 }
+#[test]
+fn ramp() {
+    assert_eq!(
+        Ok((
+            "",
+            Atom::VRamp(20, Span::Notes(NonZeroU16::new(8).unwrap()))
+        )),
+        super::ramp(&format!("{RAMP}{VOLUME}20{RAMP_NOTES}8"))
+    );
+    assert_eq!(
+        Ok(("", Atom::VRamp(0, Span::Length(NonZeroU8::new(2).unwrap())))),
+        super::ramp(&format!("{RAMP}{VOLUME}0{LENGTH}2"))
+    );
+}
+#[test]
+fn gate() {
+    assert_eq!(
+        Ok(("", Atom::G(NonZeroU8::new(80).unwrap()))),
+        super::gate(&format!("{GATE}80"))
+    );
+    assert!(super::gate(&format!("{GATE}0")).is_err(), "silent notes");
+    assert!(
+        super::gate(&format!("{GATE}101")).is_err(),
+        "overflowing notes"
+    );
+}
+#[test]
+fn articulation() {
+    assert_eq!(
+        Ok((
+            "",
+            Atom::Articulation(NonZeroU8::new(STACCATO_GATE).unwrap())
+        )),
+        super::staccato(&format!("{STACCATO}"))
+    );
+    assert_eq!(
+        Ok(("", Atom::Articulation(NonZeroU8::new(LEGATO_GATE).unwrap()))),
+        super::legato(&format!("{LEGATO}"))
+    );
+}
+#[test]
+fn parameter() {
+    assert_eq!(
+        Ok(("", Atom::P("pan".to_string(), -30.0))),
+        super::parameter(&format!("{PARAM}pan{PARAM_VALUE}-30"))
+    );
+    assert_eq!(
+        Ok(("a", Atom::P("duty_2".to_string(), 0.25))),
+        super::parameter(&format!("{PARAM}duty_2{PARAM_VALUE}0.25a"))
+    );
+    assert_eq!(
+        Ok((".a", Atom::P("pan".to_string(), 30.0))),
+        super::parameter(&format!("{PARAM}pan{PARAM_VALUE}30.a")),
+        "a rest right after the value"
+    );
+    assert_eq!(
+        Ok(("e3", Atom::P("x".to_string(), 1.0))),
+        super::parameter(&format!("{PARAM}x{PARAM_VALUE}1e3")),
+        "no exponents"
+    );
+    assert!(super::parameter(&format!("{PARAM}2{PARAM_VALUE}1")).is_err());
+    assert!(super::parameter(&format!("{PARAM}x{PARAM_VALUE}.25")).is_err());
+    assert!(super::parameter(&format!("{PARAM}x{PARAM_VALUE}inf")).is_err());
+}
+#[test]
+fn nested() {
+    let input = format!("{LOOP_IN}3a{LOOP_IN}b{LOOP_OUT}{LOOP_OUT}");
+    assert_eq!(
+        Ok((
+            "",
+            Atom::Loop(
+                NonZeroU16::new(3).unwrap(),
+                vec![
+                    Atom::At(2),
+                    Atom::N(0, NonZeroUsize::new(1).unwrap()),
+                    Atom::At(3),
+                    Atom::Loop(
+                        NonZeroU16::new(2).unwrap(),
+                        vec![Atom::At(4), Atom::N(1, NonZeroUsize::new(1).unwrap())]
+                    )
+                ]
+            )
+        )),
+        super::r#loop("abcde", &input)(&input)
+    );
+}
+#[test]
+fn syntax_error() {
+    assert_eq!(
+        Err(SyntaxError {
+            offset: 3,
+            found: Some('z')
+        }),
+        Atom::parse("ab z", "abcde"),
+        "unknown note"
+    );
+    assert_eq!(
+        Err(SyntaxError {
+            offset: 4,
+            found: Some('z')
+        }),
+        Atom::parse(&format!("a{LOOP_IN}2bz{LOOP_OUT}"), "abcde"),
+        "inside a loop"
+    );
+    assert_eq!(
+        Err(SyntaxError {
+            offset: 1,
+            found: Some(TUP_IN)
+        }),
+        Atom::parse(&format!("a{TUP_IN}bc"), "abcde"),
+        "unclosed tuplet"
+    );
+}
+#[test]
+fn degree() {
+    assert_eq!(
+        Ok(("", Atom::Degree(NonZeroU8::new(3).unwrap()))),
+        super::degree(&format!("{DEGREE}3"))
+    );
+    assert!(super::degree(&format!("{DEGREE}0")).is_err(), "no degree 0");
+}
+#[test]
+fn scale() {
+    let n = |n| Atom::N(n, NonZeroUsize::new(1).unwrap());
+    let notes: crate::Notes = serde_json::from_str(
+        r#"{"set": "aAbcCdDefFgG", "score": "&1&3&8&9&6", "scale": "cdefgab"}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        vec![
+            n(3),
+            n(7),
+            Atom::OIncr,
+            n(3),
+            Atom::ODecr,
+            Atom::OIncr,
+            n(5),
+            Atom::ODecr,
+            Atom::OIncr,
+            n(0),
+            Atom::ODecr,
+        ],
+        notes.flat_iter().collect::<Vec<Atom>>(),
+        "C major from c, wrapping past g"
+    );
+    let notes = crate::Notes::parse("abcde", "&2&6").unwrap();
+    assert_eq!(
+        vec![n(1), Atom::OIncr, n(0), Atom::ODecr],
+        notes.flat_iter().collect::<Vec<Atom>>(),
+        "chromatic without a scale"
+    );
+    assert!(serde_json::from_str::<crate::Notes>(
+        r#"{"set": "abc", "score": "&1", "scale": "az"}"#
+    )
+    .is_err());
+}
+#[test]
+fn mark() {
+    assert_eq!(
+        Ok(("", Atom::Mark(Mark::ToCoda))),
+        super::mark(&format!("{MARK}{TO_CODA}"))
+    );
+    assert_eq!(
+        Ok(("", Atom::Mark(Mark::Coda))),
+        super::mark(&format!("{MARK}{CODA}"))
+    );
+    assert_eq!(
+        Ok(("a", Atom::Mark(Mark::DalSegno))),
+        super::mark(&format!("{MARK}{DAL_SEGNO}a"))
+    );
+    assert!(super::mark(&format!("{MARK}x")).is_err());
+}
+#[test]
+fn bar() {
+    let score = format!("a{BAR}");
+    assert_eq!(Ok(("", Atom::Bar(1))), super::bar(&score)(&score[1..]));
+    assert_eq!(
+        Ok(vec![
+            Atom::At(0),
+            Atom::Loop(
+                NonZeroU16::new(2).unwrap(),
+                vec![
+                    Atom::At(1),
+                    Atom::N(0, NonZeroUsize::new(1).unwrap()),
+                    Atom::At(2),
+                    Atom::Bar(2)
+                ]
+            ),
+            Atom::At(4),
+            Atom::Bar(4)
+        ]),
+        Atom::parse(&format!("(a{BAR}){BAR}"), "a"),
+        "offsets in the score"
+    );
+}
+#[test]
+fn euclidean() {
+    let pattern = |hits, steps| {
+        super::euclidean(hits, steps)
+            .into_iter()
+            .map(|hit| if hit { 'x' } else { '.' })
+            .collect::<String>()
+    };
+    assert_eq!("x..x..x.", pattern(3, 8));
+    assert_eq!("x.xx.xx.", pattern(5, 8));
+    assert_eq!("x.x.x.x.", pattern(4, 8));
+    assert_eq!("....", pattern(0, 4));
+    assert_eq!("xxx", pattern(3, 3));
+}
+#[test]
+fn euclid() {
+    let hit = |phrase: Vec<Atom>| Atom::Tuplet(phrase);
+    let rest = || Atom::Rest(NonZeroUsize::new(1).unwrap());
+    let c = || Atom::N(2, NonZeroUsize::new(1).unwrap());
+    let at = Atom::At;
+    let input = format!("{EUCLID_IN}2{EUCLID_SEP}5{EUCLID_PHRASE}c{EUCLID_OUT}");
+    assert_eq!(
+        Ok((
+            "",
+            Atom::Loop(
+                NonZeroU16::new(1).unwrap(),
+                vec![
+                    hit(vec![at(5), c()]),
+                    rest(),
+                    hit(vec![at(5), c()]),
+                    rest(),
+                    rest()
+                ]
+            )
+        )),
+        super::euclid("abcde", &input)(&input)
+    );
+    let input =
+        format!("{EUCLID_IN}2{EUCLID_SEP}4{EUCLID_SEP}1{EUCLID_PHRASE}c{OCTAVEINCR}c{EUCLID_OUT}");
+    let phrase = vec![at(7), c(), at(8), Atom::OIncr, at(9), c()];
+    assert_eq!(
+        Ok((
+            "",
+            Atom::Loop(
+                NonZeroU16::new(1).unwrap(),
+                vec![rest(), hit(phrase.clone()), rest(), hit(phrase)]
+            )
+        )),
+        super::euclid("abcde", &input)(&input),
+        "rotated phrase"
+    );
+    let input = format!("{EUCLID_IN}5{EUCLID_SEP}4{EUCLID_PHRASE}c{EUCLID_OUT}");
+    assert!(
+        super::euclid("abcde", &input)(&input).is_err(),
+        "more hits than steps"
+    );
+}
+#[test]
+fn chance() {
+    let input = format!("{RANDOM}30c");
+    assert_eq!(
+        Ok((
+            "",
+            Atom::Chance(30, Box::new(Atom::N(2, NonZeroUsize::new(1).unwrap())))
+        )),
+        super::chance("abcde", &input)(&input)
+    );
+    let input = format!("{RANDOM}101c");
+    assert!(super::chance("abcde", &input)(&input).is_err());
+}
+#[test]
+fn choice() {
+    let input = format!(
+        "{RANDOM}{LOOP_IN}a{CHOICE_SEP}{RANDOM}{LOOP_IN}b{CHOICE_SEP}c{LOOP_OUT}{LOOP_OUT}"
+    );
+    assert_eq!(
+        Ok((
+            "",
+            Atom::Choice(vec![
+                vec![Atom::At(2), Atom::N(0, NonZeroUsize::new(1).unwrap())],
+                vec![
+                    Atom::At(4),
+                    Atom::Choice(vec![
+                        vec![Atom::At(6), Atom::N(1, NonZeroUsize::new(1).unwrap())],
+                        vec![Atom::At(8), Atom::N(2, NonZeroUsize::new(1).unwrap())]
+                    ])
+                ]
+            ])
+        )),
+        super::choice("abcde", &input)(&input),
+        "nested separators are ignored"
+    );
+}
+#[test]
+fn ornaments() {
+    let n = |n| Box::new(Atom::N(n, NonZeroUsize::new(1).unwrap()));
+    let input = format!("{GRACE}a c");
+    assert_eq!(
+        Ok(("", Atom::Grace(vec![*n(0)], n(2)))),
+        super::grace("abcde", &input)(&input)
+    );
+    let input = format!("{GRACE}{LOOP_IN}ab{LOOP_OUT}{DEGREE}1");
+    assert_eq!(
+        Ok((
+            "",
+            Atom::Grace(
+                vec![Atom::At(2), *n(0), Atom::At(3), *n(1)],
+                Box::new(Atom::Degree(NonZeroU8::new(1).unwrap()))
+            )
+        )),
+        super::grace("abcde", &input)(&input)
+    );
+    let input = format!("{TRILL}cd");
+    assert_eq!(
+        Ok(("", Atom::Trill(n(2), n(3)))),
+        super::trill("abcde")(&input)
+    );
+    let input = format!("{MORDENT}cb");
+    assert_eq!(
+        Ok(("", Atom::Mordent(n(2), n(1)))),
+        super::mordent("abcde")(&input)
+    );
+    let input = format!("{MORDENT}c{REST}");
+    assert!(super::mordent("abcde")(&input).is_err(), "notes only");
+}
+#[test]
+fn arpeggio() {
+    let n = |n| Atom::N(n, NonZeroUsize::new(1).unwrap());
+    let input =
+        format!("{EUCLID_IN}{OCTAVEINCR}{OCTAVEDECR}6{EUCLID_PHRASE}ce {OCTAVEINCR}a{EUCLID_OUT}");
+    assert_eq!(
+        Ok((
+            "",
+            Atom::Arpeggio(
+                Direction::UpDown,
+                NonZeroU8::new(6).unwrap(),
+                vec![n(2), n(4), Atom::OIncr, n(0)]
+            )
+        )),
+        super::arpeggio("abcde")(&input)
+    );
+    let input = format!("{EUCLID_IN}{OCTAVEDECR}3{EUCLID_PHRASE}c{LENGTH}2{EUCLID_OUT}");
+    assert!(super::arpeggio("abcde")(&input).is_err(), "pitches only");
+}
+#[test]
+fn dynamic() {
+    assert_eq!(
+        Ok(("", Atom::Dynamic(Dynamic::Ff))),
+        super::volume(&format!("{VOLUME}ff"))
+    );
+    assert_eq!(
+        Ok(("", Atom::Dynamic(Dynamic::Sffz))),
+        super::volume(&format!("{VOLUME}sffz"))
+    );
+    assert_eq!(
+        Ok(("a", Atom::Dynamic(Dynamic::Mp))),
+        super::volume(&format!("{VOLUME}mpa"))
+    );
+    assert!(super::volume(&format!("{VOLUME}x")).is_err());
+    assert_eq!(
+        Ok(vec![Atom::At(0), Atom::Dynamic(Dynamic::Ff)]),
+        Atom::parse(&format!("{VOLUME}ff"), "abcdefg"),
+        "the longest marking wins"
+    );
+    assert_eq!(
+        Ok(vec![
+            Atom::At(0),
+            Atom::Dynamic(Dynamic::F),
+            Atom::At(3),
+            Atom::N(5, NonZeroUsize::new(1).unwrap())
+        ]),
+        Atom::parse(&format!("{VOLUME}f f"), "abcdefg"),
+        "a space ends the marking"
+    );
+}
+#[test]
+fn interval() {
+    assert_eq!(
+        Ok(("", Atom::Interval(3))),
+        super::interval(&format!("{DEGREE}{INTERVAL_UP}3"))
+    );
+    assert_eq!(
+        Ok(("", Atom::Interval(-12))),
+        super::interval(&format!("{DEGREE}{INTERVAL_DOWN}12"))
+    );
+    assert!(super::interval(&format!("{DEGREE}3")).is_err(), "degree");
+}
@@ -1,4 +1,6 @@
-use crate::structure::Atom;
+use crate::structure::{Atom, Dynamic, SyntaxError};
+use std::collections::HashMap;
+use std::num::NonZeroU8;
 
 use super::*;
 
@@ -12,6 +14,10 @@ impl<'de> Deserialize<'de> for Notes {
         enum Field {
             Set,
             Score,
+            Scale,
+            Time,
+            Seed,
+            Dynamics,
         }
 
         struct NotesVisitor;
@@ -20,7 +26,8 @@ impl<'de> Deserialize<'de> for Notes {
             type Value = Notes;
 
             fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-                formatter.write_str("set and score")
+                formatter
+                    .write_str("set, score, and optional scale, time signature, seed and dynamics")
             }
 
             fn visit_seq<A>(self, mut seq: A) -> std::result::Result<Self::Value, A::Error>
@@ -30,12 +37,21 @@ impl<'de> Deserialize<'de> for Notes {
                 let set: String = seq
                     .next_element()?
                     .ok_or_else(|| Error::invalid_length(0, &self))?;
-                let score_str = seq
+                let score: String = seq
                     .next_element()?
                     .ok_or_else(|| Error::invalid_length(1, &self))?;
-                let score = Atom::parse(score_str, &set)
-                    .map_err(|err| Error::custom(format!("Syntax error: {}", err)))?;
-                Ok(Self::Value::new(set.len() as u8, score))
+                let scale: Option<String> = seq.next_element()?;
+                let time: Option<String> = seq.next_element()?;
+                let seed: Option<u64> = seq.next_element()?;
+                let dynamics: Option<HashMap<String, u8>> = seq.next_element()?;
+                build(
+                    &set,
+                    &score,
+                    scale.as_deref(),
+                    time.as_deref(),
+                    seed,
+                    dynamics,
+                )
             }
 
             fn visit_map<V>(self, mut map: V) -> Result<Self::Value, V::Error>
@@ -43,7 +59,11 @@ impl<'de> Deserialize<'de> for Notes {
                 V: MapAccess<'de>,
             {
                 let mut set: Option<String> = None;
-                let mut score = None;
+                let mut score: Option<String> = None;
+                let mut scale: Option<String> = None;
+                let mut time: Option<String> = None;
+                let mut seed: Option<u64> = None;
+                let mut dynamics: Option<HashMap<String, u8>> = None;
                 while let Some(key) = map.next_key()? {
                     match key {
                         Field::Set => {
@@ -56,37 +76,108 @@ impl<'de> Deserialize<'de> for Notes {
                             if score.is_some() {
                                 return Err(Error::duplicate_field("score"));
                             }
-                            let score_str = map.next_value()?;
-                            score = Some(
-                                Atom::parse(
-                                    score_str,
-                                    match set.clone() {
-                                        Some(s) => s,
-                                        None => {
-                                            let mut set: Option<String> = None;
-                                            while let Some(key) = map.next_key::<Field>()? {
-                                                if let Field::Set = key {
-                                                    set = Some(map.next_value()?)
-                                                }
-                                            }
-                                            set
-                                        }
-                                        .ok_or_else(|| Error::missing_field("set"))?,
-                                    }
-                                    .as_str(),
-                                )
-                                .map_err(|err| Error::custom(format!("Syntax error: {}", err)))?,
-                            );
+                            score = Some(map.next_value()?);
+                        }
+                        Field::Scale => {
+                            if scale.is_some() {
+                                return Err(Error::duplicate_field("scale"));
+                            }
+                            scale = Some(map.next_value()?);
+                        }
+                        Field::Time => {
+                            if time.is_some() {
+                                return Err(Error::duplicate_field("time"));
+                            }
+                            time = Some(map.next_value()?);
+                        }
+                        Field::Seed => {
+                            if seed.is_some() {
+                                return Err(Error::duplicate_field("seed"));
+                            }
+                            seed = Some(map.next_value()?);
+                        }
+                        Field::Dynamics => {
+                            if dynamics.is_some() {
+                                return Err(Error::duplicate_field("dynamics"));
+                            }
+                            dynamics = Some(map.next_value()?);
                         }
                     }
                 }
                 let set = set.ok_or_else(|| Error::missing_field("set"))?;
                 let score = score.ok_or_else(|| Error::missing_field("score"))?;
-                Ok(Self::Value::new(set.len() as u8, score))
+                build(
+                    &set,
+                    &score,
+                    scale.as_deref(),
+                    time.as_deref(),
+                    seed,
+                    dynamics,
+                )
             }
         }
 
-        const FIELDS: &[&str] = &["set", "score"];
+        /// Parse the score, the scale and the time signature once every field is known, then check the bars.
+        fn build<E: Error>(
+            set: &str,
+            score: &str,
+            scale: Option<&str>,
+            time: Option<&str>,
+            seed: Option<u64>,
+            dynamics: Option<HashMap<String, u8>>,
+        ) -> Result<Notes, E> {
+            let mut notes = Notes::parse(set, score)
+                .map_err(|err| Error::custom(format!("Syntax error: {}", err)))?;
+            if let Some(scale) = scale {
+                notes.scale = scale
+                    .chars()
+                    .filter(|c| !c.is_whitespace())
+                    .map(|c| {
+                        set.find(c).map(|n| n as u8).ok_or_else(|| {
+                            Error::custom(format!("Scale error: {c:?} is not in the set"))
+                        })
+                    })
+                    .collect::<Result<Vec<u8>, E>>()?;
+            }
+            notes.seed = seed.unwrap_or_default();
+            notes.dynamics = dynamics
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(name, volume)| {
+                    Dynamic::from_name(&name)
+                        .map(|dynamic| (dynamic, volume))
+                        .ok_or_else(|| {
+                            Error::custom(format!("Dynamics error: unknown marking {name:?}"))
+                        })
+                })
+                .collect::<Result<HashMap<Dynamic, u8>, E>>()?;
+            if let Some(time) = time {
+                notes.time = Some(Notes::time_signature(time).ok_or_else(|| {
+                    Error::custom(format!(
+                        "Time signature error: expected beats/unit, found {time:?}"
+                    ))
+                })?);
+            }
+            notes
+                .check_bars()
+                .map_err(|err| Error::custom(format!("Bar error: {}", err)))?;
+            Ok(notes)
+        }
+
+        const FIELDS: &[&str] = &["set", "score", "scale", "time", "seed", "dynamics"];
         deserializer.deserialize_struct("Notes", FIELDS, NotesVisitor)
     }
 }
+
+impl Notes {
+    /// Parse a score using the letters of a note set, as the deserializer does.
+    pub fn parse(set: &str, score: &str) -> Result<Self, SyntaxError> {
+        Ok(Self::new(set.len() as u8, Atom::parse(score, set)?))
+    }
+
+    /// Parse a time signature written as `beats/unit`, as the deserializer does.
+    pub fn time_signature(time: &str) -> Option<(NonZeroU8, NonZeroU8)> {
+        let (beats, unit) = time.split_once('/')?;
+        Some((beats.trim().parse().ok()?, unit.trim().parse().ok()?))
+    }
+}
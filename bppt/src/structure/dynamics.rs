@@ -0,0 +1,62 @@
+use super::*;
+
+/// Every marking with its name in scores and its default volume.
+const MARKINGS: [(Dynamic, &str, u8); 12] = [
+    (Dynamic::Ppp, "ppp", 10),
+    (Dynamic::Pp, "pp", 20),
+    (Dynamic::P, "p", 35),
+    (Dynamic::Mp, "mp", 50),
+    (Dynamic::Mf, "mf", 65),
+    (Dynamic::F, "f", 80),
+    (Dynamic::Ff, "ff", 90),
+    (Dynamic::Fff, "fff", 100),
+    (Dynamic::Sf, "sf", 90),
+    (Dynamic::Sfz, "sfz", 95),
+    (Dynamic::Sffz, "sffz", 100),
+    (Dynamic::Fz, "fz", 90),
+];
+
+impl Dynamic {
+    /// All markings, longest names first so that parsers can try them in order.
+    pub fn all() -> impl Iterator<Item = Dynamic> {
+        let mut markings = MARKINGS;
+        markings.sort_by_key(|(_, name, _)| std::cmp::Reverse(name.len()));
+        markings.into_iter().map(|(dynamic, _, _)| dynamic)
+    }
+
+    /// Name of the marking in scores.
+    pub fn name(self) -> &'static str {
+        MARKINGS.iter().find(|(d, _, _)| *d == self).unwrap().1
+    }
+
+    /// Marking with the given name, if any.
+    pub fn from_name(name: &str) -> Option<Self> {
+        MARKINGS
+            .iter()
+            .find(|(_, n, _)| *n == name)
+            .map(|(d, _, _)| *d)
+    }
+
+    /// Volume of the marking when the score doesn't give one.
+    pub fn default_volume(self) -> u8 {
+        MARKINGS.iter().find(|(d, _, _)| *d == self).unwrap().2
+    }
+
+    /// Whether the marking only applies to the next note (sforzando...).
+    pub fn is_accent(self) -> bool {
+        matches!(
+            self,
+            Dynamic::Sf | Dynamic::Sfz | Dynamic::Sffz | Dynamic::Fz
+        )
+    }
+}
+
+impl Notes {
+    /// Volume of a dynamic marking in this score.
+    pub fn volume(&self, dynamic: Dynamic) -> u8 {
+        self.dynamics
+            .get(&dynamic)
+            .copied()
+            .unwrap_or(dynamic.default_volume())
+    }
+}
@@ -0,0 +1,71 @@
+use super::Atom;
+use std::fmt::{Display, Formatter};
+use std::num::NonZeroU8;
+
+/// A score that couldn't be parsed.
+#[derive(PartialEq, Debug, Clone)]
+pub struct SyntaxError {
+    /// Byte offset of the first character that couldn't be parsed in the score.
+    pub offset: usize,
+    /// The character at that offset, if the score didn't end early.
+    pub found: Option<char>,
+}
+
+impl Display for SyntaxError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.found {
+            Some(c) => write!(f, "unexpected {c:?} at byte {}", self.offset),
+            None => write!(f, "unexpected end of score at byte {}", self.offset),
+        }
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
+/// A score whose bars don't match its time signature.
+#[derive(PartialEq, Debug, Clone)]
+pub enum BarError {
+    /// A bar lasting longer or shorter than the time signature says
+    Length {
+        /// Number of the bar in playing order, starting from 1.
+        bar: usize,
+        /// Byte offset of the bar line ending the bar in the score, or `None` if the bar runs to the end of the score.
+        offset: Option<usize>,
+        /// Duration of the bar in whole notes, as a reduced fraction.
+        duration: (u64, u64),
+        /// The time signature, as beats and beat unit.
+        expected: (NonZeroU8, NonZeroU8),
+    },
+    /// A random choice whose options don't last as long as each other (or change the length differently), so that the bars would depend on the seed
+    UnevenChoice(Vec<Vec<Atom>>),
+}
+
+impl Display for BarError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BarError::Length {
+                bar,
+                offset,
+                duration,
+                expected,
+            } => {
+                match offset {
+                    Some(offset) => write!(f, "bar {bar} (ending at byte {offset})")?,
+                    None => write!(f, "bar {bar} (ending with the score)")?,
+                }
+                write!(
+                    f,
+                    " lasts {}/{} of a whole note instead of {}/{}",
+                    duration.0, duration.1, expected.0, expected.1
+                )
+            }
+            BarError::UnevenChoice(options) => write!(
+                f,
+                "the {} options of a random choice don't last as long as each other, so the bars would depend on the seed",
+                options.len()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BarError {}
@@ -1,56 +1,401 @@
 use super::*;
+use fastrand::Rng;
+
+/// Grace notes take this fraction of the note they lead to (split between them), the note keeping the rest.
+const GRACE_SHARE: usize = 4;
+/// Trills alternate in notes of this length (32nd notes), so that they are as fast whatever the length of the trilled note.
+const TRILL_SUBDIVISION: usize = 32;
+/// A mordent plays the note, its auxiliary and the note again, each taking a step of the whole note.
+const MORDENT_STEPS: usize = 8;
+
+#[cfg(test)]
+mod tests;
 
 impl Notes {
     /// Iter through non-wrapper note atoms only (no loop / tuplet...) by flattening their vectors and applying transformations on the atoms they contain.
-    pub fn flat_iter(&self) -> FlattenedNoteIterator {
-        FlattenedNoteIterator(self.score.iter().rev().cloned().collect::<Vec<Atom>>())
+    pub fn flat_iter(&self) -> FlattenedNoteIterator<'_> {
+        FlattenedNoteIterator::new(self, self.score.clone())
+    }
+
+    /// Iter through atoms of a container of the score on their own, ignoring navigation markers like inside containers.
+    pub(super) fn flat_inner(&self, atoms: Vec<Atom>) -> FlattenedNoteIterator<'_> {
+        FlattenedNoteIterator {
+            nested: true,
+            ..FlattenedNoteIterator::new(self, atoms)
+        }
+    }
+
+    /// Atoms playing the note some steps away from another one (some octaves above the current one), moving to its octave.
+    fn interval(&self, (from, octaves): (u8, i32), steps: i16) -> Vec<Atom> {
+        let set = i32::from(self.set.max(1));
+        let target = i32::from(from) + set * octaves + i32::from(steps);
+        let octaves = target.div_euclid(set);
+        let shift = match octaves >= 0 {
+            true => Atom::OIncr,
+            false => Atom::ODecr,
+        };
+        std::iter::repeat_n(shift, octaves.unsigned_abs() as usize)
+            .chain([Atom::N(
+                target.rem_euclid(set) as u8,
+                NonZeroUsize::new(1).unwrap(),
+            )])
+            .collect()
     }
+
+    /// Atoms playing a degree of the scale, moving to the right octave and back.
+    fn degree(&self, degree: NonZeroU8) -> Vec<Atom> {
+        let set = usize::from(self.set.max(1));
+        let scale = match self.scale.is_empty() {
+            true => (0..set).collect::<Vec<usize>>(),
+            false => self
+                .scale
+                .iter()
+                .scan((0, None), |(octaves, previous), &index| {
+                    if previous.is_some_and(|previous| index <= previous) {
+                        *octaves += 1;
+                    }
+                    *previous = Some(index);
+                    Some(usize::from(index) + set * *octaves)
+                })
+                .collect(),
+        };
+        let index = usize::from(u8::from(degree) - 1);
+        let absolute = scale[index % scale.len()] + set * (index / scale.len());
+        let octaves = absolute / set;
+        std::iter::repeat_n(Atom::OIncr, octaves)
+            .chain([Atom::N(
+                (absolute % set) as u8,
+                NonZeroUsize::new(1).unwrap(),
+            )])
+            .chain(std::iter::repeat_n(Atom::ODecr, octaves))
+            .collect()
+    }
+}
+
+/// Atoms of a score with the containers flattened and the navigation markers followed, see [`Notes::flat_iter`].
+pub struct FlattenedNoteIterator<'a> {
+    notes: &'a Notes,
+    /// Top level atoms, where navigation markers are followed
+    score: Vec<Atom>,
+    /// Index of the next top level atom
+    position: usize,
+    /// Atoms coming before the next top level atom, the next one last
+    stack: Vec<Atom>,
+    /// Whether a D.C. or D.S. was taken
+    jumped: bool,
+    /// Whether this iterator flattens the content of a container, where markers are ignored
+    nested: bool,
+    /// Source of the random choices, seeded by the score
+    rng: Rng,
+    /// Set index of the last note, where intervals start from, and the number of octaves it is above the current one
+    last: Option<(u8, i32)>,
+    /// Byte offset in the score of the atom the last one comes from
+    offset: Option<usize>,
+    /// Current length (as in `$4`), which trills are subdivided from
+    length: u8,
 }
 
-pub struct FlattenedNoteIterator(Vec<Atom>);
+impl<'a> FlattenedNoteIterator<'a> {
+    fn new(notes: &'a Notes, score: Vec<Atom>) -> Self {
+        Self {
+            notes,
+            score,
+            position: 0,
+            stack: vec![],
+            jumped: false,
+            nested: false,
+            rng: Rng::with_seed(notes.seed),
+            last: None,
+            offset: None,
+            length: INITIAL_LENGTH,
+        }
+    }
+
+    /// Byte offset in the score of the atom the last atom of this iterator comes from (the container or ornament it was generated by, if any).
+    ///
+    /// Scores built from atoms rather than parsed have no offsets.
+    pub fn offset(&self) -> Option<usize> {
+        self.offset
+    }
+
+    /// Flatten the content of a container, following the repeat rules and drawing from the random choices of this iterator.
+    fn inner(&mut self, atoms: Vec<Atom>) -> Vec<Atom> {
+        let mut inner = Self {
+            jumped: self.jumped,
+            nested: true,
+            rng: self.rng.clone(),
+            last: self.last,
+            length: self.length,
+            ..Self::new(self.notes, atoms)
+        };
+        let atoms = inner.by_ref().collect();
+        self.rng = inner.rng;
+        atoms
+    }
+
+    /// Keep track of the last note (and of its octave) and of the length through an atom coming out of this iterator.
+    ///
+    /// The atoms of the containers flattened by [`Self::inner`] come out again afterwards, so only then are they followed.
+    fn follow(&mut self, atom: &Atom) {
+        match atom {
+            Atom::N(n, _) => self.last = Some((*n, 0)),
+            Atom::OIncr => self.last = self.last.map(|(n, octaves)| (n, octaves - 1)),
+            Atom::ODecr => self.last = self.last.map(|(n, octaves)| (n, octaves + 1)),
+            Atom::O(_) => self.last = self.last.map(|(n, _)| (n, 0)),
+            Atom::L(l) => self.length = l.get(),
+            Atom::LIncr => self.length = self.length.saturating_mul(2),
+            Atom::LDecr => self.length = (self.length / 2).max(1),
+            _ => {}
+        }
+    }
+
+    /// Put atoms on top of the stack so that they come next, in order.
+    fn push(&mut self, mut atoms: Vec<Atom>) {
+        atoms.reverse();
+        self.stack.append(&mut atoms);
+    }
+
+    /// Steps of an arpeggio, each moving to the octave of its note and back.
+    fn arpeggio(&mut self, direction: Direction, steps: NonZeroU8, chord: Vec<Atom>) -> Vec<Atom> {
+        let mut octave = 0isize;
+        let mut notes = vec![];
+        for atom in self.inner(chord) {
+            match atom {
+                Atom::OIncr => octave += 1,
+                Atom::ODecr => octave -= 1,
+                Atom::N(n, _) => notes.push((octave, n)),
+                _ => {}
+            }
+        }
+        notes.sort();
+        notes.dedup();
+        let order: Vec<(isize, u8)> = match direction {
+            Direction::Up => notes,
+            Direction::Down => notes.into_iter().rev().collect(),
+            Direction::UpDown => {
+                let down = notes.iter().rev().skip(1);
+                let down = down
+                    .take(notes.len().saturating_sub(2))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                notes.into_iter().chain(down).collect()
+            }
+        };
+        order
+            .into_iter()
+            .cycle()
+            .take(usize::from(steps.get()))
+            .flat_map(|(octave, n)| {
+                let (up, down) = match octave >= 0 {
+                    true => (Atom::OIncr, Atom::ODecr),
+                    false => (Atom::ODecr, Atom::OIncr),
+                };
+                let shift = octave.unsigned_abs();
+                std::iter::repeat_n(up.clone(), shift)
+                    .chain([Atom::N(n, NonZeroUsize::new(1).unwrap())])
+                    .chain(std::iter::repeat_n(down, shift))
+                    .collect::<Vec<Atom>>()
+            })
+            .collect()
+    }
+
+    /// Next top level atom after a mark, or the end of the score if it is missing.
+    fn after(&self, mark: Mark) -> Option<usize> {
+        self.score
+            .iter()
+            .position(|atom| *atom == Atom::Mark(mark))
+            .map(|index| index + 1)
+    }
+
+    /// Follow a navigation marker of the top level.
+    fn navigate(&mut self, mark: Mark) {
+        match mark {
+            Mark::Segno | Mark::Coda => {}
+            Mark::Fine if self.jumped => self.position = self.score.len(),
+            Mark::ToCoda if self.jumped => {
+                self.position = self.after(Mark::Coda).unwrap_or(self.score.len())
+            }
+            Mark::DaCapo if !self.jumped => {
+                self.jumped = true;
+                self.position = 0;
+            }
+            Mark::DalSegno if !self.jumped => {
+                self.jumped = true;
+                self.position = self.after(Mark::Segno).unwrap_or(0);
+            }
+            _ => {}
+        }
+    }
+}
 
-impl Iterator for FlattenedNoteIterator {
+impl Iterator for FlattenedNoteIterator<'_> {
     type Item = Atom;
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            let next = self.0.pop();
+            let (next, top) = match self.stack.pop() {
+                Some(atom) => (Some(atom), false),
+                None => {
+                    let atom = self.score.get(self.position).cloned();
+                    self.position += 1;
+                    (atom, !self.nested)
+                }
+            };
             match next {
                 Some(Atom::Loop(repeat, v)) => {
-                    let mut v = v
+                    let repeat = match self.jumped {
+                        true => 1,
+                        false => usize::from(NonZeroUsize::from(repeat)),
+                    };
+                    let v = v
                         .iter()
                         .cloned()
                         .cycle()
-                        .take(v.len() * usize::from(NonZeroUsize::from(repeat)))
+                        .take(v.len() * repeat)
                         .collect::<Vec<Atom>>();
-                    v.reverse();
-                    self.0.append(&mut v);
+                    self.push(v);
                 }
                 Some(Atom::Tuplet(v)) => {
-                    let mut v = Notes::new(0, v).flat_iter().collect::<Vec<Atom>>();
-                    let length = v.len();
-                    v = v
+                    let v = self.inner(v);
+                    let length = units(&v);
+                    self.push(scaled(v, length));
+                }
+                Some(Atom::Grace(graces, main)) => {
+                    let graces = self.inner(graces);
+                    let last = self.last;
+                    graces.iter().for_each(|atom| self.follow(atom));
+                    let main = self.inner(vec![*main]);
+                    self.last = last;
+                    let count = graces
                         .iter()
-                        .map(|atom| {
-                            if let Atom::N(n, tup) = atom {
-                                return Atom::N(
-                                    *n,
-                                    tup.saturating_mul(NonZeroUsize::new(length).unwrap()),
-                                );
-                            } else if let Atom::Rest(tup) = atom {
-                                return Atom::Rest(
-                                    tup.saturating_mul(NonZeroUsize::new(length).unwrap()),
-                                );
-                            }
-                            atom.clone()
-                        })
+                        .filter(|atom| matches!(atom, Atom::N(..) | Atom::Rest(_)))
+                        .count()
+                        .max(1);
+                    let share = GRACE_SHARE * count;
+                    let mut v = scaled(graces, share);
+                    v.append(&mut scaled(main, share));
+                    v.extend(std::iter::repeat_n(Atom::More, share - count - 1));
+                    self.push(v);
+                }
+                Some(Atom::Trill(main, auxiliary)) => {
+                    // as many steps as 32nd notes in the current length, and at least the note and its auxiliary
+                    let steps = (TRILL_SUBDIVISION / usize::from(self.length)).max(2);
+                    let v = [*main, *auxiliary]
+                        .into_iter()
                         .cycle()
-                        .take(length)
-                        .collect::<Vec<Atom>>();
-                    v.reverse();
-                    self.0.append(&mut v);
+                        .take(steps)
+                        .collect();
+                    let v = scaled(self.inner(v), steps);
+                    self.push(v);
+                }
+                Some(Atom::Arpeggio(direction, steps, chord)) => {
+                    let v = self.arpeggio(direction, steps, chord);
+                    self.push(vec![Atom::Tuplet(v)]);
+                }
+                Some(Atom::Mordent(main, auxiliary)) => {
+                    let v = vec![*main.clone(), *auxiliary, *main];
+                    let mut v = scaled(self.inner(v), MORDENT_STEPS);
+                    v.extend(std::iter::repeat_n(Atom::More, MORDENT_STEPS - 3));
+                    self.push(v);
+                }
+                Some(Atom::Degree(degree)) => {
+                    let v = self.notes.degree(degree);
+                    self.push(v);
+                }
+                Some(Atom::Chance(p, atom)) => match self.rng.u8(..100) < p {
+                    true => self.push(vec![*atom]),
+                    false => self.push(vec![silenced(*atom)]),
+                },
+                Some(Atom::Choice(options)) => {
+                    let v = options[self.rng.usize(..options.len())].clone();
+                    self.push(v);
+                }
+                Some(Atom::Dynamic(dynamic)) => {
+                    let volume = self.notes.volume(dynamic);
+                    break Some(match dynamic.is_accent() {
+                        true => Atom::Accent(volume),
+                        false => Atom::V(volume),
+                    });
+                }
+                Some(Atom::Mark(mark)) => {
+                    if top {
+                        self.navigate(mark);
+                    }
+                }
+                // nested iterators keep the offsets for the atoms they come out again in
+                Some(Atom::At(offset)) if !self.nested => self.offset = Some(offset),
+                Some(Atom::Interval(steps)) => {
+                    let v = self.notes.interval(self.last.unwrap_or((0, 0)), steps);
+                    self.push(v);
+                }
+                other => {
+                    if let Some(atom) = &other {
+                        self.follow(atom);
+                    }
+                    break other;
                 }
-                other => break other,
             }
         }
     }
 }
+
+/// The same atom with rests instead of notes, so that it takes as long without a sound.
+fn silenced(atom: Atom) -> Atom {
+    match atom {
+        Atom::N(_, tup) => Atom::Rest(tup),
+        Atom::Degree(_) | Atom::Interval(_) => Atom::Rest(NonZeroUsize::new(1).unwrap()),
+        Atom::Loop(repeat, v) => Atom::Loop(repeat, v.into_iter().map(silenced).collect()),
+        Atom::Tuplet(v) => Atom::Tuplet(v.into_iter().map(silenced).collect()),
+        Atom::Chance(p, atom) => Atom::Chance(p, Box::new(silenced(*atom))),
+        Atom::Grace(graces, main) => Atom::Grace(
+            graces.into_iter().map(silenced).collect(),
+            Box::new(silenced(*main)),
+        ),
+        Atom::Trill(main, auxiliary) => {
+            Atom::Trill(Box::new(silenced(*main)), Box::new(silenced(*auxiliary)))
+        }
+        Atom::Mordent(main, auxiliary) => {
+            Atom::Mordent(Box::new(silenced(*main)), Box::new(silenced(*auxiliary)))
+        }
+        Atom::Arpeggio(_, steps, _) => Atom::Tuplet(vec![
+            Atom::Rest(NonZeroUsize::new(1).unwrap());
+            usize::from(steps.get())
+        ]),
+        Atom::Choice(options) => Atom::Choice(
+            options
+                .into_iter()
+                .map(|v| v.into_iter().map(silenced).collect())
+                .collect(),
+        ),
+        atom => atom,
+    }
+}
+
+/// Flattened atoms with notes and rests divided into smaller ones.
+fn scaled(atoms: Vec<Atom>, factor: usize) -> Vec<Atom> {
+    let factor = NonZeroUsize::new(factor).unwrap();
+    atoms
+        .into_iter()
+        .map(|atom| match atom {
+            Atom::N(n, tup) => Atom::N(n, tup.saturating_mul(factor)),
+            Atom::Rest(tup) => Atom::Rest(tup.saturating_mul(factor)),
+            atom => atom,
+        })
+        .collect()
+}
+
+/// Number of units (notes, rests and ties) in flattened atoms, where a nested tuplet or an ornament counts as one.
+fn units(atoms: &[Atom]) -> usize {
+    let mut tup = 1;
+    let units: f64 = atoms
+        .iter()
+        .filter_map(|atom| match atom {
+            Atom::N(_, t) | Atom::Rest(t) => {
+                tup = t.get();
+                Some(1.0 / tup as f64)
+            }
+            Atom::More => Some(1.0 / tup as f64),
+            _ => None,
+        })
+        .sum();
+    (units.round() as usize).max(1)
+}
@@ -0,0 +1,172 @@
+use crate::{Atom, Notes};
+use std::num::NonZeroUsize;
+
+/// Notes played by a flattened score, as set letters.
+fn played(score: &str) -> String {
+    Notes::parse("abcdefg", score)
+        .unwrap()
+        .flat_iter()
+        .filter_map(|atom| match atom {
+            Atom::N(n, _) => Some(char::from(b'a' + n)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// A flattened score as text: set letters with their tuplet level (when above 1), rests, ties and octave changes.
+fn flat(score: &str) -> String {
+    let level = |tup: NonZeroUsize| match tup.get() {
+        1 => String::new(),
+        tup => tup.to_string(),
+    };
+    Notes::parse("abcdefg", score)
+        .unwrap()
+        .flat_iter()
+        .map(|atom| match atom {
+            Atom::N(n, tup) => format!("{}{}", char::from(b'a' + n), level(tup)),
+            Atom::Rest(tup) => format!(".{}", level(tup)),
+            Atom::More => "+".to_string(),
+            Atom::OIncr => ">".to_string(),
+            Atom::ODecr => "<".to_string(),
+            _ => String::new(),
+        })
+        .collect()
+}
+
+#[test]
+fn da_capo() {
+    assert_eq!("abcabc", played("ab c/dc"));
+    assert_eq!("abcdab", played("ab/fine cd/dc"), "al fine");
+    assert_eq!("abcdabe", played("ab/tocoda cd/dc /coda e"), "al coda");
+}
+
+#[test]
+fn dal_segno() {
+    assert_eq!("abcbc", played("a/segno bc/ds"));
+    assert_eq!("abcdbc", played("a/segno bc/fine d/ds"), "al fine");
+    assert_eq!(
+        "abcdbcf",
+        played("a/segno bc/tocoda d/ds e/coda f"),
+        "al coda"
+    );
+    assert_eq!("abab", played("ab/ds"), "missing segno");
+}
+
+#[test]
+fn repeats() {
+    assert_eq!("aabcabc", played("(a)bc/dc"), "loops once after a jump");
+    assert_eq!("aabcabc", played("[(a)b]c/dc/ds"), "jumps once");
+    assert_eq!("aab", played("(a/dc)b"), "top level only");
+}
+
+#[test]
+fn randomness() {
+    let seeded = |seed, score| {
+        let mut notes = Notes::parse("abcdefg", score).unwrap();
+        notes.seed = seed;
+        notes.flat_iter().collect::<Vec<Atom>>()
+    };
+    assert_eq!(
+        seeded(7, "(16?(a;b;c))"),
+        seeded(7, "(16?(a;b;c))"),
+        "reproducible"
+    );
+    assert_ne!(
+        seeded(7, "(16?(a;b;c))"),
+        seeded(8, "(16?(a;b;c))"),
+        "seeded"
+    );
+    let choices = played("(32?(a;b;c))");
+    assert_eq!(32, choices.len());
+    assert!(
+        choices.contains('a') && choices.contains('b') && choices.contains('c'),
+        "picked every time"
+    );
+    assert_eq!("aaaa", played("(4?100a)"));
+    assert_eq!("", played("(4?0a)"));
+    let rests = seeded(0, "?0[ab]")
+        .into_iter()
+        .filter(|atom| matches!(atom, Atom::Rest(_)))
+        .count();
+    assert_eq!(2, rests, "silenced in time");
+}
+
+#[test]
+fn ornaments() {
+    assert_eq!("b4c4++", flat(",b c"), "grace note");
+    assert_eq!("a8b8c8+++++", flat(",(ab)c"), "grace notes");
+    assert_eq!("c8d8c8d8c8d8c8d8", flat("~cd"), "trill");
+    assert_eq!("c2d2", flat("$16~cd"), "short trill");
+    assert_eq!("c32d32".repeat(16), flat("$1~cd"), "whole note trill");
+    assert_eq!("c8b8c8+++++", flat("-cb"), "mordent");
+    assert_eq!("a2b8c8++", flat("[a,bc]"), "in a tuplet");
+    assert_eq!("a2b4c4", flat("[a[bc]]"), "nested tuplets");
+}
+
+#[test]
+fn arpeggios() {
+    assert_eq!("c4e4g4c4", flat("{>4:gec}"), "up");
+    assert_eq!("g3e3c3", flat("{<3:ceg}"), "down");
+    assert_eq!("c6e6g6>c6<g6e6", flat("{><6:ce g>c}"), "up-down");
+    assert_eq!("c2c2", flat("{><2:c}"), "single note");
+}
+
+#[test]
+fn dynamics() {
+    let notes: Notes = serde_json::from_str(
+        r#"{"set": "abc", "score": "!p a !sfz b !ff c", "dynamics": {"ff": 120}}"#,
+    )
+    .unwrap();
+    let volumes = notes
+        .flat_iter()
+        .filter_map(|atom| match atom {
+            Atom::V(v) => Some(format!("v{v}")),
+            Atom::Accent(v) => Some(format!("a{v}")),
+            _ => None,
+        })
+        .collect::<Vec<String>>();
+    assert_eq!(vec!["v35", "a95", "v120"], volumes);
+    assert!(serde_json::from_str::<Notes>(
+        r#"{"set": "a", "score": "a", "dynamics": {"fffff": 1}}"#
+    )
+    .is_err());
+}
+
+#[test]
+fn intervals() {
+    assert_eq!("ceg", flat("c&+2&+2"));
+    assert_eq!("f>ba", flat("f &+3 &-1"), "crossing octaves up");
+    assert_eq!("b<g>a", flat("b &-2 &+1"), "crossing octaves down");
+    assert_eq!("c", flat("&+2"), "from the first note of the set");
+    assert_eq!("ab2c2d", flat("a[&+1&+1]&+1"), "through tuplets");
+    let notes: Notes =
+        serde_json::from_str(r#"{"set": "abcdefg", "score": "&8&+1", "scale": "cdefgab"}"#)
+            .unwrap();
+    assert_eq!(
+        vec![
+            Atom::OIncr,
+            Atom::N(2, NonZeroUsize::new(1).unwrap()),
+            Atom::ODecr,
+            Atom::OIncr,
+            Atom::N(3, NonZeroUsize::new(1).unwrap()),
+        ],
+        notes.flat_iter().collect::<Vec<Atom>>(),
+        "from a degree an octave up"
+    );
+}
+
+#[test]
+fn offsets() {
+    let located = |score| {
+        let notes = Notes::parse("abcdefg", score).unwrap();
+        let mut atoms = notes.flat_iter();
+        std::iter::from_fn(|| atoms.next().map(|_| atoms.offset().unwrap())).collect::<Vec<_>>()
+    };
+    assert_eq!(vec![0, 3, 4, 3, 4], located("a (b<)"), "repeated atoms");
+    assert_eq!(vec![1, 2, 5, 1, 2, 5], located("[ab] c/dc"), "jumps");
+    assert_eq!(vec![0, 0, 0], located("&8"), "generated atoms");
+    let built = Notes::new(7, vec![Atom::N(0, NonZeroUsize::new(1).unwrap())]);
+    let mut atoms = built.flat_iter();
+    atoms.next();
+    assert_eq!(None, atoms.offset(), "built scores");
+}